@@ -1,3 +1,23 @@
+let units = "imperial";
+let timers = [];
+let headlines = [];
+let headlineIndex = 0;
+
+// Open the mirror once as /?token=... and the key is remembered on this display.
+const params = new URLSearchParams(window.location.search);
+if (params.has("token")) {
+    localStorage.setItem("mirrorToken", params.get("token"));
+}
+const token = localStorage.getItem("mirrorToken");
+
+function api(path, options = {}) {
+    const headers = { ...(options.headers || {}) };
+    if (token) {
+        headers["Authorization"] = `Bearer ${token}`;
+    }
+    return fetch(path, { ...options, headers });
+}
+
 function updateTime() {
     const now = new Date();
     document.getElementById("time").innerText = now.toLocaleTimeString([], { hour: '2-digit', minute: '2-digit' });
@@ -5,37 +25,52 @@ function updateTime() {
 }
 
 async function getWeather() {
-    const API_URL = "http://localhost:3000/weather?location=Orange,CA";
+    const API_URL = "/weather";
 
     try {
-        const response = await fetch(API_URL);
+        const response = await api(API_URL);
         if (!response.ok) {
             throw new Error(`HTTP error! Status: ${response.status}`);
         }
 
         const data = await response.json();
-        document.getElementById("weather").innerHTML = 
-            `${data.location.name}, ${data.location.region} <br> Temp:${data.current.temp_f}°F <br> Condition:${data.current.condition.text}`;
-            
-        document.getElementById("weather-icon").src = data.current.condition.icon;
+        renderWeather(data);
     
     } catch (error) {
         console.error("Error fetching weather data:", error);
     }
 }
 
+function renderWeather(data) {
+    const temp = units === "metric" ? `${data.current.temp_c}°C` : `${data.current.temp_f}°F`;
+    // Place names come from the location search, so they only go in as textContent.
+    const lines = [`${data.location.name}, ${data.location.region}`, `Temp:${temp}`, `Condition:${data.current.condition.text}`];
+    document.getElementById("weather").replaceChildren(...lines.flatMap((line, i) => i === 0 ? [line] : [document.createElement("br"), line]));
+
+    document.getElementById("weather-icon").src = data.current.condition.icon;
+}
+
 
 async function updateCalendar() {
     console.log("Called Update Calendar");
     const events = await getCalendarEvents();
     console.log("EVENTS FROM UPDATE CALENDAR:", events);
-    document.getElementById("events").innerHTML = events.map(e => `<li>${e.start}:<br> ${e.title}</li>`).join("");
+    renderEvents(events);
+}
+
+// Titles come from shared calendars, anyone who can send an invite writes them, so they only go in as textContent.
+function renderEvents(events) {
+    document.getElementById("events").replaceChildren(...events.map(e => {
+        const entry = document.createElement("li");
+        entry.append(`${e.start}:`, document.createElement("br"), ` ${e.title}`);
+        return entry;
+    }));
 }
 
 async function getCalendarEvents() {
-    const API_URL = "http://localhost:3000/calendar";
+    const API_URL = "/calendar";
     try {
-        const response = await fetch(API_URL);
+        const response = await api(API_URL);
         if (!response.ok) {
             throw new Error(`HTTP error! Status: ${response.status}`);
         }
@@ -64,11 +99,222 @@ function parseEvents(events) {
     });
 }
 
+async function getTimers() {
+    try {
+        const response = await api("/timers");
+        if (!response.ok) {
+            throw new Error(`HTTP error! Status: ${response.status}`);
+        }
+
+        timers = await response.json();
+        renderTimers();
+
+    } catch (error) {
+        console.error("Error fetching timers:", error);
+    }
+}
+
+function formatDuration(ms) {
+    const total = Math.max(0, Math.round(ms / 1000));
+    const hours = Math.floor(total / 3600);
+    const minutes = String(Math.floor(total % 3600 / 60)).padStart(2, "0");
+    const seconds = String(total % 60).padStart(2, "0");
+    return hours > 0 ? `${hours}:${minutes}:${seconds}` : `${minutes}:${seconds}`;
+}
+
+// Counts down locally between pushes; the server decides when something rings.
+function renderTimers() {
+    const now = Date.now();
+    // Labels come from whoever set the timer, so they only go in as textContent.
+    const container = document.getElementById("timers");
+    container.replaceChildren();
+    for (const timer of timers) {
+        const name = timer.label ? `${timer.label} ${timer.kind}` : timer.kind;
+        let value;
+        if (timer.ringing) {
+            value = "ringing";
+        } else if (timer.kind === "alarm") {
+            value = new Date(timer.ends_at).toLocaleTimeString([], { hour: '2-digit', minute: '2-digit' });
+        } else if (timer.ends_at) {
+            value = formatDuration(new Date(timer.ends_at) - now);
+        } else {
+            value = formatDuration(now - new Date(timer.started_at));
+        }
+        const entry = document.createElement("li");
+        entry.textContent = `${name}: ${value}`;
+        if (timer.ringing) {
+            entry.className = "ringing";
+            const dismiss = document.createElement("button");
+            dismiss.textContent = "Stop";
+            dismiss.onclick = () => dismissTimer(timer.id);
+            entry.append(" ", dismiss);
+        }
+        container.appendChild(entry);
+    }
+}
+
+async function dismissTimer(id) {
+    try {
+        await api(`/timers/${id}/stop`, { method: "POST" });
+    } catch (error) {
+        console.error("Error dismissing timer:", error);
+    }
+}
+
+async function getLists() {
+    try {
+        const response = await api("/lists");
+        if (!response.ok) {
+            throw new Error(`HTTP error! Status: ${response.status}`);
+        }
+
+        renderLists(await response.json());
+
+    } catch (error) {
+        console.error("Error fetching lists:", error);
+    }
+}
+
+// Lists can be edited from chat, MQTT and the API, so their text only ever goes in as textContent.
+function renderLists(lists) {
+    const container = document.getElementById("lists");
+    container.replaceChildren();
+    for (const [name, items] of Object.entries(lists)) {
+        const heading = document.createElement("h3");
+        heading.textContent = name;
+        const list = document.createElement("ul");
+        for (const item of items) {
+            const entry = document.createElement("li");
+            entry.textContent = item.text;
+            if (item.done) {
+                entry.className = "done";
+            }
+            list.appendChild(entry);
+        }
+        container.append(heading, list);
+    }
+}
+
+async function getNews() {
+    try {
+        const response = await api("/news");
+        if (!response.ok) {
+            throw new Error(`HTTP error! Status: ${response.status}`);
+        }
+
+        headlines = await response.json();
+        rotateHeadline();
+
+    } catch (error) {
+        console.error("Error fetching news:", error);
+    }
+}
+
+function rotateHeadline() {
+    if (headlines.length === 0) {
+        document.getElementById("news").innerText = "";
+        return;
+    }
+    headlineIndex = (headlineIndex + 1) % headlines.length;
+    const headline = headlines[headlineIndex];
+    document.getElementById("news").innerText = `${headline.title} (${headline.source})`;
+}
+
+async function getActiveProfile() {
+    try {
+        const response = await api("/profiles/active");
+        if (!response.ok) {
+            throw new Error(`HTTP error! Status: ${response.status}`);
+        }
+
+        const profile = await response.json();
+        units = profile.units;
+        getWeather();
+
+    } catch (error) {
+        console.error("Error fetching active profile:", error);
+    }
+}
+
+function showNotification(text) {
+    document.getElementById("notification").innerText = text;
+}
+
+async function playClip(clipId, text) {
+    try {
+        const response = await api(`/clips/${clipId}`);
+        if (!response.ok) {
+            throw new Error(`HTTP error! Status: ${response.status}`);
+        }
+
+        const audioBlob = await response.blob();
+        const audio = new Audio(URL.createObjectURL(audioBlob));
+
+        showNotification(text);
+        audio.onended = () => showNotification("");
+        audio.play();
+
+    } catch (error) {
+        console.error("Error playing clip:", error);
+        showNotification(text);
+    }
+}
+
+function listenForEvents() {
+    const source = new EventSource(token ? `/events?token=${encodeURIComponent(token)}` : "/events");
+
+    source.onmessage = (message) => {
+        const event = JSON.parse(message.data);
+        switch (event.type) {
+            case "weather":
+                renderWeather(event.weather);
+                break;
+            case "calendar":
+                renderEvents(parseEvents(event.events));
+                break;
+            case "alert":
+                showNotification(event.text);
+                break;
+            case "speak":
+                playClip(event.clip_id, event.text);
+                break;
+            case "speaking":
+                showNotification(event.text);
+                break;
+            case "timers":
+                timers = event.timers;
+                renderTimers();
+                break;
+            case "lists":
+                renderLists(event.lists);
+                break;
+            case "news":
+                headlines = event.headlines;
+                rotateHeadline();
+                break;
+            case "interaction":
+                break;
+            case "active_user":
+                units = event.units;
+                showNotification(`Hi ${event.name}`);
+                getWeather();
+                updateCalendar();
+                break;
+            default:
+                console.log("Unknown event:", event);
+        }
+    };
+
+    source.onerror = (error) => {
+        console.error("Event stream error:", error);
+    };
+}
+
 // async function sendMessage() {
 //     const userInput = document.getElementById("userInput").value;
 //     if (!userInput.trim()) return;
 
-//     const API_URL = "http://localhost:3000/chat";
+//     const API_URL = "/chat";
 
 //     try {
 //         const response = await fetch(API_URL, {
@@ -103,10 +349,10 @@ async function executeCommand(command){
     const userInput = command;
     if (!userInput.trim()) return;
 
-    const API_URL = "http://localhost:3000/chat"; 
+    const API_URL = "/chat?format=json"; 
 
     try {
-        const response = await fetch(API_URL, {
+        const response = await api(API_URL, {
             method: "POST",
             headers: {
                 "Content-Type": "application/json"
@@ -114,23 +360,45 @@ async function executeCommand(command){
             body: JSON.stringify({ message: userInput })
         });
 
+        if (response.status === 503) {
+            // Speech synthesis is down, show the answer instead of speaking it.
+            const reply = await response.json();
+            showNotification(reply.text);
+            handleChatCommand(reply);
+            return;
+        }
         if (!response.ok) {
             throw new Error(`HTTP error! Status: ${response.status}`);
         }
 
-        const audioBlob = await response.blob(); 
+        const reply = await response.json();
+        const audioBytes = Uint8Array.from(atob(reply.audio_base64), c => c.charCodeAt(0));
+        const audioBlob = new Blob([audioBytes], { type: reply.content_type });
         const audioUrl = URL.createObjectURL(audioBlob); 
 
         const audio = new Audio(audioUrl);
+        showNotification(reply.text);
+        audio.onended = () => showNotification("");
         audio.play();
 
-        document.getElementById("response").innerHTML = `<strong>Playing AI Response...</strong>`;
-        document.getElementById("userInput").value = "";
-        startWakeWordRecognition();
+        handleChatCommand(reply);
 
     } catch (error) {
         console.error("Error sending message:", error);
-        document.getElementById("response").innerHTML = "Error communicating with AI.";
+        showNotification("Error communicating with AI.");
+    }
+}
+
+function handleChatCommand(reply) {
+    switch (reply.command) {
+        case "add_event":
+            updateCalendar();
+            break;
+        case "play_song":
+            console.log("Requested song:", reply.parameters.song);
+            break;
+        default:
+            break;
     }
 }
 
@@ -192,6 +460,7 @@ function activationWord () {
         // Restart wake word recognition after command processing
         commandRecognition.onend = () => {
             console.log('Command processing ended.');
+            startWakeWordRecognition();
         };
 
         // Error handling
@@ -203,17 +472,22 @@ function activationWord () {
         commandRecognition.onerror = (event) => {
             console.error('Command recognition error:', event.error);
             alert('Sorry, I did not understand that. Please try again.');
-            startWakeWordRecognition();
         };
     }
 }
 
 
 setInterval(updateTime, 60000);
+setInterval(renderTimers, 1000);
+setInterval(rotateHeadline, 10000);
 setInterval(getWeather, 3600000);
 setInterval(updateCalendar, 3600000);
 
 activationWord();
+listenForEvents();
 updateTime();
-getWeather();
+getActiveProfile();
 updateCalendar();
+getTimers();
+getLists();
+getNews();
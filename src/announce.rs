@@ -0,0 +1,172 @@
+use axum::{Json, extract::State};
+use serde::{Deserialize, Serialize};
+use std::{sync::{Arc, Mutex}, time::Duration};
+use tokio::{sync::Notify, time::Instant};
+
+use crate::push::{self, MirrorEvent};
+use crate::state::AppState;
+use crate::text_to_speech;
+use crate::voices;
+
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Announcement {
+    pub text: String,
+    /// Voice profile name, the session's voice when unset.
+    #[serde(default)]
+    pub voice: Option<String>,
+    #[serde(default)]
+    pub priority: Priority,
+}
+
+/// Announcements waiting their turn, and until when someone else has the floor.
+pub struct AnnounceQueue {
+    waiting: Vec<(u64, Announcement)>,
+    next_id: u64,
+    busy_until: Instant,
+    /// Conversations still waiting on their reply, see `ConversationHold`.
+    conversations: usize,
+    ready: Arc<Notify>,
+}
+
+impl Default for AnnounceQueue {
+    fn default() -> Self {
+        AnnounceQueue {
+            waiting: Vec::new(),
+            next_id: 0,
+            busy_until: Instant::now(),
+            conversations: 0,
+            ready: Arc::new(Notify::new()),
+        }
+    }
+}
+
+impl AnnounceQueue {
+    /// Queues an announcement and returns its id and how many are ahead of it.
+    pub fn push(&mut self, announcement: Announcement) -> (u64, usize) {
+        self.next_id += 1;
+        let ahead = self.waiting.iter().filter(|(_, waiting)| waiting.priority >= announcement.priority).count();
+        self.waiting.push((self.next_id, announcement));
+        self.ready.notify_one();
+        (self.next_id, ahead)
+    }
+
+    /// Highest priority first, oldest first within a priority.
+    fn pop(&mut self) -> Option<Announcement> {
+        let position = self.waiting.iter()
+            .enumerate()
+            .max_by_key(|(position, (_, announcement))| (announcement.priority, std::cmp::Reverse(*position)))
+            .map(|(position, _)| position)?;
+        Some(self.waiting.remove(position).1)
+    }
+
+    /// Keeps announcements quiet for at least `duration` from now.
+    pub fn hold(&mut self, duration: Duration) {
+        self.busy_until = self.busy_until.max(Instant::now() + duration);
+    }
+}
+
+/// Rough playback length, displays don't report back when a clip ends.
+pub fn speaking_time(text: &str) -> Duration {
+    let words = text.split_whitespace().count() as f64;
+    Duration::from_secs_f64((words / 2.5 + 1.0).min(90.0))
+}
+
+/// Keeps announcements quiet while a conversation waits on its reply, however long the model takes.
+/// Dropping it, once the reply is out or has failed, gives the floor back.
+pub struct ConversationHold {
+    queue: Arc<Mutex<AnnounceQueue>>,
+}
+
+impl Drop for ConversationHold {
+    fn drop(&mut self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.conversations -= 1;
+        queue.ready.notify_one();
+    }
+}
+
+pub fn conversation_started(app_state: &AppState) -> ConversationHold {
+    app_state.announcements.lock().unwrap().conversations += 1;
+    ConversationHold { queue: app_state.announcements.clone() }
+}
+
+/// Keeps announcements quiet for a while, e.g. while someone who said the wake phrase gets to their command.
+pub fn hold(app_state: &AppState, duration: Duration) {
+    app_state.announcements.lock().unwrap().hold(duration);
+}
+
+pub fn hold_for(app_state: &AppState, text: &str) {
+    app_state.announcements.lock().unwrap().hold(speaking_time(text));
+}
+
+pub fn enqueue(app_state: &AppState, text: &str, priority: Priority) -> (u64, usize) {
+    app_state.announcements.lock().unwrap().push(Announcement {
+        text: text.to_string(),
+        voice: None,
+        priority,
+    })
+}
+
+/// Speaks queued announcements one at a time, never over a conversation or each other.
+pub async fn run_queue(app_state: AppState) {
+    let ready = app_state.announcements.lock().unwrap().ready.clone();
+    loop {
+        let (busy_until, talking) = {
+            let queue = app_state.announcements.lock().unwrap();
+            (queue.busy_until, queue.conversations > 0)
+        };
+        if talking {
+            ready.notified().await;
+            continue;
+        }
+        if busy_until > Instant::now() {
+            tokio::time::sleep_until(busy_until).await;
+            continue;
+        }
+        let Some(announcement) = app_state.announcements.lock().unwrap().pop() else {
+            ready.notified().await;
+            continue;
+        };
+
+        let voice = voices::select(&app_state, announcement.voice.as_deref(), None);
+        match text_to_speech::synthesize(&app_state, &announcement.text, &voice, None).await {
+            Ok(speech) => {
+                let clip_id = app_state.clips.lock().unwrap().insert(speech);
+                push::publish(&app_state, MirrorEvent::Speak { clip_id, text: announcement.text.clone() });
+            },
+            Err(err) => {
+                tracing::warn!("Failed to synthesize announcement: {}", err);
+                push::publish(&app_state, MirrorEvent::Alert { text: announcement.text.clone() });
+            }
+        }
+        hold_for(&app_state, &announcement.text);
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct AnnounceResponse {
+    pub id: u64,
+    /// Announcements that will play first.
+    pub ahead: usize,
+}
+
+pub async fn post_announce(
+    State(app_state): State<AppState>,
+    Json(announcement): Json<Announcement>
+) -> Result<(http::StatusCode, Json<AnnounceResponse>), http::StatusCode> {
+    if announcement.text.trim().is_empty() {
+        return Err(http::StatusCode::BAD_REQUEST);
+    }
+    let (id, ahead) = app_state.announcements.lock().unwrap().push(announcement);
+    Ok((http::StatusCode::ACCEPTED, Json(AnnounceResponse { id, ahead })))
+}
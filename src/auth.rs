@@ -0,0 +1,313 @@
+use axum::{Json, extract::{ConnectInfo, Path, State}, middleware::Next, response::{IntoResponse, Response}};
+use chrono::{DateTime, Utc};
+use dotenv::dotenv;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{env, fs, net::SocketAddr};
+
+use crate::state::AppState;
+
+
+/// What a key may do; each scope includes the ones before it.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Read-only displays: weather, calendar, events, clips and the like.
+    Display,
+    /// Talking to the assistant and changing timers, lists and announcements.
+    Chat,
+    /// Managing keys.
+    Admin,
+}
+
+impl Scope {
+    fn parse(scope: &str) -> Option<Scope> {
+        match scope.trim().to_lowercase().as_str() {
+            "display" | "read" => Some(Scope::Display),
+            "chat" => Some(Scope::Chat),
+            "admin" => Some(Scope::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// A key as listed, only the hash of its token is kept.
+#[derive(Serialize, Debug, Clone)]
+pub struct ApiKey {
+    pub name: String,
+    pub scope: Scope,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub created_at: Option<DateTime<Utc>>,
+    /// Keys from `API_KEYS` live in config and can't be revoked over the API.
+    pub from_config: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredKey {
+    name: String,
+    scope: Scope,
+    token_hash: String,
+    created_at: Option<DateTime<Utc>>,
+}
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn keys_path() -> String {
+    dotenv().ok();
+    env::var("API_KEYS_PATH").unwrap_or_else(|_| "api_keys.json".to_string())
+}
+
+/// `API_KEYS` entries look like `name:scope:token`, comma separated.
+fn config_keys() -> Vec<ApiKey> {
+    dotenv().ok();
+    env::var("API_KEYS").unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let mut parts = entry.splitn(3, ':');
+            let (Some(name), Some(scope), Some(token)) = (parts.next(), parts.next(), parts.next()) else {
+                tracing::warn!("Ignoring API_KEYS entry without name:scope:token");
+                return None;
+            };
+            let Some(scope) = Scope::parse(scope) else {
+                tracing::warn!("Ignoring API key {} with unknown scope {}", name, scope);
+                return None;
+            };
+            Some(ApiKey {
+                name: name.trim().to_string(),
+                scope,
+                token_hash: hash_token(token.trim()),
+                created_at: None,
+                from_config: true,
+            })
+        })
+        .collect()
+}
+
+/// Keys from config plus the ones made through `/keys`, which persist in `API_KEYS_PATH`.
+#[derive(Default)]
+pub struct KeyStore {
+    keys: Vec<ApiKey>,
+}
+
+impl KeyStore {
+    pub fn load() -> KeyStore {
+        let mut keys = config_keys();
+        let path = keys_path();
+        if let Ok(contents) = fs::read_to_string(&path) {
+            match serde_json::from_str::<Vec<StoredKey>>(&contents) {
+                Ok(stored) => keys.extend(stored.into_iter().map(|key| ApiKey {
+                    name: key.name,
+                    scope: key.scope,
+                    token_hash: key.token_hash,
+                    created_at: key.created_at,
+                    from_config: false,
+                })),
+                Err(err) => tracing::warn!("Error parsing API keys in {}: {}", path, err),
+            }
+        }
+        if keys.is_empty() {
+            tracing::warn!("No API keys configured, only this machine can use the server until one is added");
+        }
+        KeyStore { keys }
+    }
+
+    fn save(&self) {
+        let stored: Vec<StoredKey> = self.keys.iter()
+            .filter(|key| !key.from_config)
+            .map(|key| StoredKey {
+                name: key.name.clone(),
+                scope: key.scope,
+                token_hash: key.token_hash.clone(),
+                created_at: key.created_at,
+            })
+            .collect();
+        let result = serde_json::to_string_pretty(&stored)
+            .map_err(std::io::Error::from)
+            .and_then(|contents| fs::write(keys_path(), contents));
+        if let Err(err) = result {
+            tracing::error!("Failed to save API keys: {}", err);
+        }
+    }
+
+    /// Without any keys only loopback clients get in, so a fresh install can mint its first key locally.
+    pub fn enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    pub fn check(&self, token: &str) -> Option<&ApiKey> {
+        let hash = hash_token(token);
+        self.keys.iter().find(|key| key.token_hash == hash)
+    }
+
+    pub fn list(&self) -> Vec<ApiKey> {
+        self.keys.clone()
+    }
+
+    /// Makes a key and returns its token, which is never shown again.
+    pub fn create(&mut self, name: &str, scope: Scope) -> Result<String, String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("A key needs a name".to_string());
+        }
+        if self.keys.iter().any(|key| key.name == name) {
+            return Err(format!("There is already a key called {}", name));
+        }
+        let mut bytes = [0u8; 24];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        self.keys.push(ApiKey {
+            name: name.to_string(),
+            scope,
+            token_hash: hash_token(&token),
+            created_at: Some(Utc::now()),
+            from_config: false,
+        });
+        self.save();
+        Ok(token)
+    }
+
+    pub fn revoke(&mut self, name: &str) -> Result<(), String> {
+        let key = self.keys.iter().find(|key| key.name == name).ok_or(format!("No key called {}", name))?;
+        if key.from_config {
+            return Err(format!("{} comes from API_KEYS, remove it there", name));
+        }
+        self.keys.retain(|key| key.name != name);
+        self.save();
+        Ok(())
+    }
+}
+
+/// The mirror UI's own files, the only thing served without a key. Anything else, including routes added later,
+/// needs at least `Scope::Display`.
+fn is_static_asset(method: &http::Method, path: &str) -> bool {
+    if method != http::Method::GET && method != http::Method::HEAD {
+        return false;
+    }
+    // Only top-level files, so `/lists/notes.js` stays an API call.
+    let Some(file) = path.strip_prefix('/').filter(|file| !file.contains('/')) else {
+        return false;
+    };
+    file.is_empty() || file.rsplit_once('.').is_some_and(|(_, extension)| {
+        matches!(extension, "html" | "js" | "css" | "png" | "jpg" | "svg" | "ico" | "woff2" | "webmanifest")
+    })
+}
+
+/// The scope a request needs, None for the mirror UI's static files.
+fn required_scope(method: &http::Method, path: &str) -> Option<Scope> {
+    if is_static_asset(method, path) {
+        return None;
+    }
+    let section = path.trim_start_matches('/').split('/').next().unwrap_or_default();
+    match section {
+        "keys" | "usage" => Some(Scope::Admin),
+        // Talking to the assistant costs money, even over the websocket.
+        "chat" | "transcribe" | "listen" | "announce" | "briefing" => Some(Scope::Chat),
+        // Silencing a ringing timer is what the display's own button does.
+        "timers" if method == http::Method::POST && path.ends_with("/stop") => Some(Scope::Display),
+        _ if method == http::Method::GET || method == http::Method::HEAD => Some(Scope::Display),
+        _ => Some(Scope::Chat),
+    }
+}
+
+/// `Authorization: Bearer <token>`, or `?token=` for EventSource, audio and websockets which can't set headers.
+fn request_token<B>(request: &http::Request<B>) -> Option<String> {
+    let header = request.headers()
+        .get(http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+    header.or_else(|| {
+        request.uri().query()?
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("token="))
+            .map(|token| urlencoding::decode(token).map(|t| t.into_owned()).unwrap_or_else(|_| token.to_string()))
+    })
+}
+
+/// Which key made a request, for anything downstream that counts per client.
+#[derive(Debug, Clone)]
+pub struct ClientName(pub String);
+
+/// Middleware: rejects requests whose key is missing or lacks the route's scope.
+pub async fn require_key<B>(
+    State(app_state): State<AppState>,
+    mut request: http::Request<B>,
+    next: Next<B>
+) -> Response {
+    // Preflights carry no credentials; CORS decides those.
+    if request.method() == http::Method::OPTIONS {
+        return next.run(request).await;
+    }
+    let Some(scope) = required_scope(request.method(), request.uri().path()) else {
+        return next.run(request).await;
+    };
+    let allowed = {
+        let keys = app_state.api_keys.lock().unwrap();
+        if !keys.enabled() {
+            // Fail closed: a display on this machine keeps working, anything else on the network waits for a key.
+            let local = request.extensions().get::<ConnectInfo<SocketAddr>>()
+                .is_some_and(|info| info.0.ip().is_loopback());
+            if local { None } else { Some(None) }
+        } else {
+            Some(request_token(&request).and_then(|token| keys.check(&token).map(|key| (key.name.clone(), key.scope))))
+        }
+    };
+    match allowed {
+        None => next.run(request).await,
+        Some(Some((name, granted))) if granted >= scope => {
+            request.extensions_mut().insert(ClientName(name));
+            next.run(request).await
+        },
+        Some(Some((name, granted))) => {
+            tracing::warn!(key = %name, scope = ?granted, needed = ?scope, "API key lacks scope");
+            http::StatusCode::FORBIDDEN.into_response()
+        },
+        Some(None) => {
+            tracing::warn!("Missing or unknown API key");
+            http::StatusCode::UNAUTHORIZED.into_response()
+        },
+    }
+}
+
+pub async fn list_keys(State(app_state): State<AppState>) -> Json<Vec<ApiKey>> {
+    Json(app_state.api_keys.lock().unwrap().list())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewKey {
+    pub name: String,
+    pub scope: Scope,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CreatedKey {
+    pub name: String,
+    pub scope: Scope,
+    pub token: String,
+}
+
+pub async fn create_key(
+    State(app_state): State<AppState>,
+    Json(new_key): Json<NewKey>
+) -> Result<(http::StatusCode, Json<CreatedKey>), (http::StatusCode, String)> {
+    let token = app_state.api_keys.lock().unwrap()
+        .create(&new_key.name, new_key.scope)
+        .map_err(|err| (http::StatusCode::BAD_REQUEST, err))?;
+    Ok((http::StatusCode::CREATED, Json(CreatedKey { name: new_key.name.trim().to_string(), scope: new_key.scope, token })))
+}
+
+pub async fn revoke_key(
+    State(app_state): State<AppState>,
+    Path(name): Path<String>
+) -> Result<http::StatusCode, (http::StatusCode, String)> {
+    app_state.api_keys.lock().unwrap()
+        .revoke(&name)
+        .map(|_| http::StatusCode::NO_CONTENT)
+        .map_err(|err| (http::StatusCode::BAD_REQUEST, err))
+}
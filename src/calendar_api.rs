@@ -1,52 +1,57 @@
 use google_calendar3::{CalendarHub, hyper, hyper_rustls, oauth2};
-use axum::{Json};
+use axum::{Json, extract::State};
 use std::{fs, path::PathBuf};
 use dotenv::dotenv;
 use serde::{Deserialize, Serialize};
 use std::env;
 use chrono::{DateTime, Utc, Duration};
 
+use crate::state::AppState;
 
-#[derive(Debug, Deserialize, Serialize)]
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct CalendarEvent {
     pub id: Option<String>,
     pub summary: Option<String>,
     pub description: Option<String>,
     pub html_link: Option<String>,
     pub status: Option<String>,
+    #[serde(default)]
+    pub location: Option<String>,
     pub start: Option<EventDateTime>,
     pub end: Option<EventDateTime>,
     pub creator: Option<EventCreator>,
     pub organizer: Option<EventOrganizer>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct EventDateTime {
     pub date: Option<String>,
     pub date_time: Option<String>, 
     pub time_zone: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct EventCreator {
     pub email: Option<String>,
     pub display_name: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct EventOrganizer {
     pub email: Option<String>,
     pub display_name: Option<String>,
 }
 
-pub async fn get_calendar_events() -> Vec<CalendarEvent> {
+/// Reads the next week of events, limited to `calendars` unless it is empty.
+pub async fn get_calendar_events(calendars: &[String]) -> Vec<CalendarEvent> {
     dotenv().ok();
     let creds_path = PathBuf::from(env::var("GOOGLE_CREDENTIALS_PATH").expect("Missing GOOGLE_CREDENTIALS_PATH"));
 
     let creds = match fs::read_to_string(&creds_path) {
         Ok(contents) => contents,
         Err(e) => {
-            eprintln!("Error reading credentials file: {}", e);
+            tracing::error!("Error reading credentials file: {}", e);
             return vec![];
         }
     };
@@ -54,7 +59,7 @@ pub async fn get_calendar_events() -> Vec<CalendarEvent> {
     let creds_json: serde_json::Value = match serde_json::from_str(&creds) {
         Ok(json) => json,
         Err(e) => {
-            eprintln!("Error parsing credentials file: {}", e);
+            tracing::error!("Error parsing credentials file: {}", e);
             return vec![];
         }
     };
@@ -62,7 +67,7 @@ pub async fn get_calendar_events() -> Vec<CalendarEvent> {
     let secret: oauth2::ApplicationSecret = match serde_json::from_value(creds_json["installed"].clone()) {
         Ok(secret) => secret,
         Err(e) => {
-            eprintln!("Error extracting OAuth secret: {}", e);
+            tracing::error!("Error extracting OAuth secret: {}", e);
             return vec![];
         }
     };
@@ -77,7 +82,7 @@ pub async fn get_calendar_events() -> Vec<CalendarEvent> {
     {
         Ok(auth) => auth,
         Err(e) => {
-            eprintln!("Error during authentication: {}", e);
+            tracing::error!("Error during authentication: {}", e);
             return vec![];
         }
     };
@@ -98,7 +103,7 @@ pub async fn get_calendar_events() -> Vec<CalendarEvent> {
     let calendar_list = match hub.calendar_list().list().doit().await {
         Ok((_resp, calendar_list)) => calendar_list.items.unwrap_or_default(),
         Err(e) => {
-            eprintln!("Error fetching calendar list: {}", e);
+            tracing::warn!("Error fetching calendar list: {}", e);
             return vec![];
         }
     };
@@ -107,6 +112,9 @@ pub async fn get_calendar_events() -> Vec<CalendarEvent> {
 
     for calendar in calendar_list {
         if let Some(calendar_id) = calendar.id {
+            if !calendars.is_empty() && !calendars.contains(&calendar_id) {
+                continue;
+            }
             let result = hub
                 .events()
                 .list(&calendar_id)
@@ -130,6 +138,7 @@ pub async fn get_calendar_events() -> Vec<CalendarEvent> {
                                 description: event.description,
                                 html_link: event.html_link,
                                 status: event.status,
+                                location: event.location,
                                 start: event.start.map(|s| EventDateTime {
                                     date: s.date,
                                     date_time: s.date_time,
@@ -153,7 +162,7 @@ pub async fn get_calendar_events() -> Vec<CalendarEvent> {
                     }
                 }
                 Err(e) => {
-                    eprintln!("Error fetching events for calendar: {}", e);
+                    tracing::warn!("Error fetching events for calendar: {}", e);
                 },
             }
         }
@@ -163,20 +172,27 @@ pub async fn get_calendar_events() -> Vec<CalendarEvent> {
 }
 
 
-pub async fn get_calendar_json() -> Json<Vec<CalendarEvent>> {
-    Json(get_calendar_events().await)
+pub async fn get_calendar_json(State(app_state): State<AppState>) -> Json<Vec<CalendarEvent>> {
+    let profile = crate::profiles::resolve(&app_state, None);
+    Json(get_calendar_events(&profile.calendars).await)
+}
+
+/// Calendar new events go to when a profile doesn't name one.
+pub fn default_calendar_id() -> String {
+    dotenv().ok();
+    env::var("GOOGLE_CALENDAR_ID").unwrap_or_else(|_| "c_02f23b407241b05d9235403f1821745ee55848bcc35019ed95ca20c3551e5d5b@group.calendar.google.com".to_string())
 }
 
-pub async fn add_event(event: google_calendar3::api::Event) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn add_event(event: google_calendar3::api::Event, calendar_id: &str) -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
     let event_json = serde_json::to_string(&event)?;
-    println!("Sending event JSON: {}", event_json);
-    let creds_path = PathBuf::from(env::var("GOOGLE_CREDENTIALS_PATH").expect("Missing GOOGLE_CREDENTIALS_PATH"));
+    tracing::debug!(event = %crate::logging::content(&event_json), "Sending event");
+    let creds_path = PathBuf::from(env::var("GOOGLE_CREDENTIALS_PATH").map_err(|_| "GOOGLE_CREDENTIALS_PATH is not set")?);
 
     let creds = match fs::read_to_string(&creds_path) {
         Ok(contents) => contents,
         Err(e) => {
-            eprintln!("Error reading credentials file: {}", e);
+            tracing::error!("Error reading credentials file: {}", e);
             return Err(Box::new(e));
         }
     };
@@ -184,7 +200,7 @@ pub async fn add_event(event: google_calendar3::api::Event) -> Result<(), Box<dy
     let creds_json: serde_json::Value = match serde_json::from_str(&creds) {
         Ok(json) => json,
         Err(e) => {
-            eprintln!("Error parsing credentials file: {}", e);
+            tracing::error!("Error parsing credentials file: {}", e);
             return Err(Box::new(e));
         }
     };
@@ -192,7 +208,7 @@ pub async fn add_event(event: google_calendar3::api::Event) -> Result<(), Box<dy
     let secret: oauth2::ApplicationSecret = match serde_json::from_value(creds_json["installed"].clone()) {
         Ok(secret) => secret,
         Err(e) => {
-            eprintln!("Error extracting OAuth secret: {}", e);
+            tracing::error!("Error extracting OAuth secret: {}", e);
             return Err(Box::new(e));
         }
     };
@@ -207,7 +223,7 @@ pub async fn add_event(event: google_calendar3::api::Event) -> Result<(), Box<dy
     {
         Ok(auth) => auth,
         Err(e) => {
-            eprintln!("Error during authentication: {}", e);
+            tracing::error!("Error during authentication: {}", e);
             return Err(Box::new(e));
         }
     };
@@ -220,17 +236,15 @@ pub async fn add_event(event: google_calendar3::api::Event) -> Result<(), Box<dy
 
     let hub = CalendarHub::new(hyper::Client::builder().build(https), auth);
 
-    let result = hub.events().insert(event, "c_02f23b407241b05d9235403f1821745ee55848bcc35019ed95ca20c3551e5d5b@group.calendar.google.com").doit().await;
+    let result = hub.events().insert(event, calendar_id).doit().await;
 
     match result {
         Ok((response, event)) => {
-            println!("Event successfully created!");
-            println!("HTTP Status: {}", response.status());
-            println!("Event Link: {}", event.html_link.unwrap_or_default());
-            println!("Server Response Headers: {:?}", response.headers());
+            // Response headers and the event link stay out of the logs, they carry session and personal details.
+            tracing::info!(status = response.status().as_u16(), id = %event.id.unwrap_or_default(), "Event created");
         }
         Err(e) => {
-            eprintln!("Error creating event: {}", e);
+            tracing::error!("Error creating event: {}", e);
         }
     }
 
@@ -239,7 +253,7 @@ pub async fn add_event(event: google_calendar3::api::Event) -> Result<(), Box<dy
 
 pub fn create_basic_event(name: String, start: String, end: String) -> google_calendar3::api::Event {
 
-    let event = google_calendar3::api::Event {
+    google_calendar3::api::Event {
         summary: Some(name),
         description: Some("Made by MirrorAI".to_string()),
         visibility: Some("public".to_string()),
@@ -252,6 +266,5 @@ pub fn create_basic_event(name: String, start: String, end: String) -> google_ca
             ..Default::default()
         }),
         ..Default::default()
-    };
-    return event;
+    }
 }
\ No newline at end of file
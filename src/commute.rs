@@ -0,0 +1,255 @@
+use axum::{Json, extract::{Query, State}};
+use chrono::{DateTime, Duration, Utc};
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, env};
+
+use crate::announce::{self, Priority};
+use crate::calendar_api::{self, CalendarEvent};
+use crate::places;
+use crate::profiles;
+use crate::state::AppState;
+use crate::weather_api::{self, Geocoded};
+
+
+pub type CommuteError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Point {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Route {
+    pub duration: Duration,
+    pub distance_km: f64,
+}
+
+/// Who works out driving times, picked with `ROUTING_PROVIDER`.
+pub enum RoutingProvider {
+    Osrm { base_url: String },
+    GraphHopper { base_url: String, api_key: String },
+}
+
+impl RoutingProvider {
+    pub fn from_env() -> Option<RoutingProvider> {
+        dotenv().ok();
+        match env::var("ROUTING_PROVIDER").unwrap_or_else(|_| "osrm".to_string()).as_str() {
+            "osrm" => Some(RoutingProvider::Osrm {
+                base_url: env::var("OSRM_URL").unwrap_or_else(|_| "https://router.project-osrm.org".to_string()),
+            }),
+            "graphhopper" => Some(RoutingProvider::GraphHopper {
+                base_url: env::var("GRAPHHOPPER_URL").unwrap_or_else(|_| "https://graphhopper.com/api/1".to_string()),
+                // Self-hosted GraphHopper doesn't need a key.
+                api_key: env::var("GRAPHHOPPER_API_KEY").unwrap_or_default(),
+            }),
+            other => {
+                tracing::warn!("Unknown ROUTING_PROVIDER: {}", other);
+                None
+            }
+        }
+    }
+
+    pub async fn route(&self, from: Point, to: Point) -> Result<Route, CommuteError> {
+        match self {
+            RoutingProvider::Osrm { base_url } => route_osrm(base_url, from, to).await,
+            RoutingProvider::GraphHopper { base_url, api_key } => route_graphhopper(base_url, api_key, from, to).await,
+        }
+    }
+}
+
+/// Turns an upstream failure into something safe to show the caller or the model.
+/// reqwest errors print the request URL, and with it keys like GraphHopper's `key=`.
+fn unreachable<E: Into<CommuteError>>(service: &'static str) -> impl Fn(E) -> CommuteError {
+    move |err| {
+        tracing::warn!(error = %crate::logging::without_url(err.into()), "Request to the {} failed", service);
+        format!("couldn't reach the {}", service).into()
+    }
+}
+
+async fn route_osrm(base_url: &str, from: Point, to: Point) -> Result<Route, CommuteError> {
+    let url = format!(
+        "{}/route/v1/driving/{},{};{},{}",
+        base_url.trim_end_matches('/'), from.lon, from.lat, to.lon, to.lat
+    );
+    let body: serde_json::Value = async {
+        reqwest::Client::new()
+            .get(url)
+            .query(&[("overview", "false")])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }.await.map_err(unreachable("routing service"))?;
+    let route = &body["routes"][0];
+    let seconds = route["duration"].as_f64().ok_or("OSRM returned no route")?;
+    Ok(Route {
+        duration: Duration::seconds(seconds.round() as i64),
+        distance_km: route["distance"].as_f64().unwrap_or_default() / 1000.0,
+    })
+}
+
+async fn route_graphhopper(base_url: &str, api_key: &str, from: Point, to: Point) -> Result<Route, CommuteError> {
+    let mut query = vec![
+        ("point", format!("{},{}", from.lat, from.lon)),
+        ("point", format!("{},{}", to.lat, to.lon)),
+        ("profile", "car".to_string()),
+        ("calc_points", "false".to_string()),
+    ];
+    if !api_key.is_empty() {
+        query.push(("key", api_key.to_string()));
+    }
+    let body: serde_json::Value = async {
+        reqwest::Client::new()
+            .get(format!("{}/route", base_url.trim_end_matches('/')))
+            .query(&query)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }.await.map_err(unreachable("routing service"))?;
+    let path = &body["paths"][0];
+    let millis = path["time"].as_i64().ok_or("GraphHopper returned no route")?;
+    Ok(Route {
+        duration: Duration::milliseconds(millis),
+        distance_km: path["distance"].as_f64().unwrap_or_default() / 1000.0,
+    })
+}
+
+/// Extra minutes added to every trip for parking, getting out the door and so on.
+fn buffer() -> Duration {
+    dotenv().ok();
+    Duration::minutes(env::var("COMMUTE_BUFFER_MINUTES").ok().and_then(|m| m.parse().ok()).unwrap_or(5))
+}
+
+/// Saved places, coordinates and anything the location search can find.
+pub async fn resolve_point(location: &str) -> Result<Point, CommuteError> {
+    let query = match weather_api::geocode(location).await.map_err(unreachable("location search"))? {
+        Geocoded::Found(query) => query,
+        Geocoded::Ambiguous(candidates) => {
+            let options: Vec<String> = candidates.iter().take(5).map(|c| c.label()).collect();
+            return Err(format!("\"{}\" could be {}", location, options.join("; ")).into());
+        },
+        Geocoded::NotFound => return Err(format!("No place called \"{}\" was found", location).into()),
+    };
+    let (lat, lon) = query.split_once(',').ok_or("Location has no coordinates")?;
+    Ok(Point { lat: lat.trim().parse()?, lon: lon.trim().parse()? })
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Commute {
+    pub from: String,
+    pub to: String,
+    pub duration_minutes: i64,
+    pub distance_km: f64,
+    pub arrive_by: Option<DateTime<Utc>>,
+    pub leave_by: Option<DateTime<Utc>>,
+    pub advice: String,
+}
+
+fn clock(time: DateTime<Utc>) -> String {
+    time.with_timezone(&places::home_now().timezone()).format("%-I:%M %p").to_string()
+}
+
+/// Travel time between two locations and, given an arrival time, when to set off.
+/// Errors never carry upstream URLs, so they can go back to callers and the model as they are.
+pub async fn plan(from: &str, to: &str, arrive_by: Option<DateTime<Utc>>) -> Result<Commute, CommuteError> {
+    let provider = RoutingProvider::from_env().ok_or("No routing provider configured")?;
+    let route = provider.route(resolve_point(from).await?, resolve_point(to).await?).await?;
+    let minutes = (route.duration.num_seconds() + 59) / 60;
+    let leave_by = arrive_by.map(|at| at - route.duration - buffer());
+    let advice = match leave_by {
+        Some(leave_by) if leave_by <= Utc::now() => format!("It's {} minutes to {}, you should leave now", minutes, to),
+        Some(leave_by) => format!("It's {} minutes to {}, leave by {}", minutes, to, clock(leave_by)),
+        None => format!("It's {} minutes to {} right now", minutes, to),
+    };
+    Ok(Commute {
+        from: from.to_string(),
+        to: to.to_string(),
+        duration_minutes: minutes,
+        distance_km: (route.distance_km * 10.0).round() / 10.0,
+        arrive_by,
+        leave_by,
+        advice,
+    })
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CommuteQuery {
+    pub to: Option<String>,
+    pub from: Option<String>,
+    /// "08:30" home time, or a full "yyyy-mm-ddTHH:MM".
+    pub arrive_by: Option<String>,
+}
+
+pub async fn get_commute(
+    State(app_state): State<AppState>,
+    Query(query): Query<CommuteQuery>
+) -> Result<Json<Commute>, (http::StatusCode, String)> {
+    let to = query.to.unwrap_or_else(|| "work".to_string());
+    let from = query.from
+        .or(profiles::resolve(&app_state, None).location)
+        .unwrap_or_else(|| "home".to_string());
+    let arrive_by = match query.arrive_by.as_deref().map(crate::timers::parse_alarm_time) {
+        Some(None) => return Err((http::StatusCode::BAD_REQUEST, "arrive_by should look like 08:30".to_string())),
+        Some(at) => at,
+        None => None,
+    };
+    plan(&from, &to, arrive_by).await
+        .map(Json)
+        .map_err(|err| (http::StatusCode::BAD_GATEWAY, err.to_string()))
+}
+
+fn event_start(event: &CalendarEvent) -> Option<DateTime<Utc>> {
+    let start = event.start.as_ref()?.date_time.as_ref()?;
+    Some(DateTime::parse_from_rfc3339(start).ok()?.with_timezone(&Utc))
+}
+
+/// Warns before events that have a location: "leave by 2:40 for the dentist".
+/// `DEPARTURE_REMINDER_MINUTES` is how early, 0 turns it off.
+pub async fn run_departures(app_state: AppState) {
+    dotenv().ok();
+    let lead = Duration::minutes(env::var("DEPARTURE_REMINDER_MINUTES").ok().and_then(|m| m.parse().ok()).unwrap_or(10));
+    if lead <= Duration::zero() || env::var("GOOGLE_CREDENTIALS_PATH").is_err() || places::home().is_none() {
+        tracing::warn!("Departure reminders disabled");
+        return;
+    }
+
+    let mut announced: HashMap<String, DateTime<Utc>> = HashMap::new();
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(5 * 60));
+    loop {
+        interval.tick().await;
+        let now = Utc::now();
+        announced.retain(|_, start| *start > now);
+        let profile = profiles::resolve(&app_state, None);
+        for event in calendar_api::get_calendar_events(&profile.calendars).await {
+            let (Some(id), Some(location), Some(start)) = (event.id.clone(), event.location.clone(), event_start(&event)) else {
+                continue;
+            };
+            // Nobody drives more than a few hours to something on the mirror's calendar.
+            if start <= now || start - now > Duration::hours(4) || announced.contains_key(&id) {
+                continue;
+            }
+            let commute = match plan("home", &location, Some(start)).await {
+                Ok(commute) => commute,
+                Err(err) => {
+                    tracing::warn!(destination = %crate::logging::content(&location), "Failed to plan trip: {}", err);
+                    continue;
+                }
+            };
+            let Some(leave_by) = commute.leave_by else {
+                continue;
+            };
+            if leave_by - lead > now {
+                continue;
+            }
+            announced.insert(id, start);
+            let summary = event.summary.unwrap_or_else(|| "your next event".to_string());
+            let text = format!("Leave by {} for {}, it's a {} minute drive.", clock(leave_by), summary, commute.duration_minutes);
+            announce::enqueue(&app_state, &text, Priority::High);
+        }
+    }
+}
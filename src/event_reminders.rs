@@ -0,0 +1,240 @@
+use chrono::{DateTime, Duration, Utc};
+use dotenv::dotenv;
+use std::collections::HashSet;
+use std::env;
+
+use crate::announce::{self, Priority};
+use crate::calendar_api::{self, CalendarEvent};
+use crate::profiles::{self, UserProfile};
+use crate::state::AppState;
+
+
+#[derive(Clone, Debug)]
+pub struct Reminder {
+    pub event_id: String,
+    pub summary: String,
+    pub starts_at: DateTime<Utc>,
+}
+
+impl Reminder {
+    pub fn spoken_text(&self, now: DateTime<Utc>) -> String {
+        let seconds_left = (self.starts_at - now).num_seconds().max(0);
+        let minutes_left = (seconds_left + 59) / 60;
+        match minutes_left {
+            0 => format!("Reminder: {} is starting now.", self.summary),
+            1 => format!("Reminder: {} starts in 1 minute.", self.summary),
+            n => format!("Reminder: {} starts in {} minutes.", self.summary, n),
+        }
+    }
+}
+
+/// Tracks which calendar events have been announced so each lead time fires once.
+#[derive(Default)]
+pub struct ReminderState {
+    upcoming: Vec<Reminder>,
+    announced: HashSet<(String, i64)>,
+    dismissed: HashSet<String>,
+    snoozed: Vec<(DateTime<Utc>, Reminder)>,
+    /// Reminders already spoken and not yet snoozed or dismissed, oldest first.
+    spoken: Vec<Reminder>,
+}
+
+impl ReminderState {
+    pub fn set_upcoming(&mut self, upcoming: Vec<Reminder>) {
+        let ids: HashSet<&String> = upcoming.iter().map(|r| &r.event_id).collect();
+        self.announced.retain(|(id, _)| ids.contains(id));
+        self.dismissed.retain(|id| ids.contains(id));
+        self.spoken.retain(|r| ids.contains(&r.event_id));
+        self.upcoming = upcoming;
+    }
+
+    pub fn take_due(&mut self, now: DateTime<Utc>, lead_times: &[i64]) -> Vec<Reminder> {
+        let mut due = Vec::new();
+
+        for reminder in &self.upcoming {
+            if reminder.starts_at <= now || self.dismissed.contains(&reminder.event_id) {
+                continue;
+            }
+            let mut fresh = false;
+            for lead in lead_times {
+                if reminder.starts_at - Duration::minutes(*lead) <= now
+                    && self.announced.insert((reminder.event_id.clone(), *lead))
+                {
+                    fresh = true;
+                }
+            }
+            if fresh {
+                due.push(reminder.clone());
+            }
+        }
+
+        let (ready, waiting): (Vec<_>, Vec<_>) = self.snoozed.drain(..).partition(|(at, _)| *at <= now);
+        self.snoozed = waiting;
+        for (_, reminder) in ready {
+            if !self.dismissed.contains(&reminder.event_id) {
+                due.push(reminder);
+            }
+        }
+
+        due
+    }
+
+    pub fn spoken(&mut self, reminder: Reminder) {
+        self.spoken.retain(|r| r.event_id != reminder.event_id);
+        self.spoken.push(reminder);
+    }
+
+    /// The spoken reminder whose event is named by `event`, else the most recent one.
+    fn take_spoken(&mut self, event: Option<&str>) -> Option<Reminder> {
+        let event = event.map(str::trim).filter(|e| !e.is_empty()).map(str::to_lowercase);
+        let index = match event {
+            Some(event) => self.spoken.iter().rposition(|r| r.summary.to_lowercase().contains(&event))?,
+            None => self.spoken.len().checked_sub(1)?,
+        };
+        Some(self.spoken.remove(index))
+    }
+
+    /// Re-queues a spoken reminder `minutes` from now.
+    pub fn snooze(&mut self, now: DateTime<Utc>, minutes: i64, event: Option<&str>) -> Option<Reminder> {
+        let reminder = self.take_spoken(event)?;
+        self.snoozed.retain(|(_, r)| r.event_id != reminder.event_id);
+        self.snoozed.push((now + Duration::minutes(minutes), reminder.clone()));
+        Some(reminder)
+    }
+
+    /// Stops all further reminders for a spoken event.
+    pub fn dismiss(&mut self, event: Option<&str>) -> Option<Reminder> {
+        let reminder = self.take_spoken(event)?;
+        self.dismissed.insert(reminder.event_id.clone());
+        self.snoozed.retain(|(_, r)| r.event_id != reminder.event_id);
+        Some(reminder)
+    }
+}
+
+pub fn lead_times() -> Vec<i64> {
+    dotenv().ok();
+    env::var("REMINDER_LEAD_MINUTES")
+        .unwrap_or_else(|_| "15".to_string())
+        .split(',')
+        .filter_map(|lead| lead.trim().parse::<i64>().ok())
+        .collect()
+}
+
+pub fn default_snooze_minutes() -> i64 {
+    dotenv().ok();
+    env::var("REMINDER_SNOOZE_MINUTES").ok()
+        .and_then(|m| m.parse().ok())
+        .unwrap_or(5)
+}
+
+fn upcoming_from_events(events: &[CalendarEvent]) -> Vec<Reminder> {
+    events.iter().filter_map(|event| {
+        // All-day events only carry a date and are not worth a spoken countdown.
+        let start = event.start.as_ref()?.date_time.as_ref()?;
+        let starts_at = DateTime::parse_from_rfc3339(start).ok()?.with_timezone(&Utc);
+        Some(Reminder {
+            event_id: event.id.clone()?,
+            summary: event.summary.clone().unwrap_or_else(|| "An event".to_string()),
+            starts_at,
+        })
+    }).collect()
+}
+
+/// Every calendar someone in the household reads, all of them when any profile reads all.
+fn reminder_calendars(profiles: &[UserProfile]) -> Vec<String> {
+    if profiles.iter().any(|profile| profile.calendars.is_empty()) {
+        return Vec::new();
+    }
+    let mut calendars: Vec<String> = profiles.iter().flat_map(|profile| profile.calendars.clone()).collect();
+    calendars.sort();
+    calendars.dedup();
+    calendars
+}
+
+pub async fn run_scheduler(app_state: AppState) {
+    dotenv().ok();
+    let lead_times = lead_times();
+    if lead_times.is_empty() || env::var("GOOGLE_CREDENTIALS_PATH").is_err() {
+        tracing::warn!("Event reminders disabled");
+        return;
+    }
+    let refresh_every = Duration::minutes(
+        env::var("REMINDER_REFRESH_MINUTES").ok().and_then(|m| m.parse().ok()).unwrap_or(5)
+    );
+
+    let mut last_refresh: Option<DateTime<Utc>> = None;
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(30));
+    loop {
+        interval.tick().await;
+        let now = Utc::now();
+
+        if last_refresh.is_none_or(|at| now - at >= refresh_every) {
+            let calendars = reminder_calendars(&profiles::load_profiles());
+            let events = calendar_api::get_calendar_events(&calendars).await;
+            app_state.reminders.lock().unwrap().set_upcoming(upcoming_from_events(&events));
+            last_refresh = Some(now);
+        }
+
+        let due = app_state.reminders.lock().unwrap().take_due(now, &lead_times);
+        for reminder in due {
+            let text = reminder.spoken_text(now);
+            announce::enqueue(&app_state, &text, Priority::Normal);
+            app_state.reminders.lock().unwrap().spoken(reminder);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(calendars: &[&str]) -> UserProfile {
+        serde_json::from_value(serde_json::json!({"id": "sam", "name": "Sam", "calendars": calendars})).unwrap()
+    }
+
+    fn reminder(id: &str, summary: &str, now: DateTime<Utc>) -> Reminder {
+        Reminder { event_id: id.to_string(), summary: summary.to_string(), starts_at: now + Duration::minutes(10) }
+    }
+
+    #[test]
+    fn snooze_and_dismiss_pick_the_named_event() {
+        let now = Utc::now();
+        let mut state = ReminderState::default();
+        state.set_upcoming(vec![reminder("a", "Dentist", now), reminder("b", "Standup", now)]);
+        for due in state.take_due(now, &[15]) {
+            state.spoken(due);
+        }
+
+        assert_eq!(state.snooze(now, 5, Some("dentist")).unwrap().event_id, "a");
+        assert_eq!(state.dismiss(None).unwrap().event_id, "b");
+        assert!(state.dismiss(None).is_none());
+
+        let due = state.take_due(now + Duration::minutes(5), &[15]);
+        assert_eq!(due.iter().map(|r| r.event_id.as_str()).collect::<Vec<_>>(), ["a"]);
+    }
+
+    #[test]
+    fn an_unknown_event_is_left_alone() {
+        let now = Utc::now();
+        let mut state = ReminderState::default();
+        state.set_upcoming(vec![reminder("a", "Dentist", now)]);
+        for due in state.take_due(now, &[15]) {
+            state.spoken(due);
+        }
+
+        assert!(state.snooze(now, 5, Some("standup")).is_none());
+        assert_eq!(state.dismiss(Some("")).unwrap().event_id, "a");
+    }
+
+    #[test]
+    fn reminders_cover_everyones_calendars() {
+        let profiles = [profile(&["sam@example.com", "family"]), profile(&["alex@example.com", "family"])];
+        assert_eq!(reminder_calendars(&profiles), ["alex@example.com", "family", "sam@example.com"]);
+    }
+
+    #[test]
+    fn a_profile_without_calendars_reads_them_all() {
+        let profiles = [profile(&["sam@example.com"]), profile(&[])];
+        assert!(reminder_calendars(&profiles).is_empty());
+    }
+}
@@ -0,0 +1,332 @@
+use axum::Json;
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use std::env;
+
+
+pub type HaError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Where Home Assistant lives and what the mirror may touch there.
+pub struct HaConfig {
+    pub url: String,
+    pub token: String,
+    /// Entity ids from `HA_ENTITIES`, `light.*` allows a whole domain.
+    pub allowed: Vec<String>,
+}
+
+impl HaConfig {
+    pub fn from_env() -> Option<HaConfig> {
+        dotenv().ok();
+        Some(HaConfig {
+            url: env::var("HA_URL").ok()?.trim_end_matches('/').to_string(),
+            token: env::var("HA_TOKEN").ok()?,
+            allowed: env::var("HA_ENTITIES").unwrap_or_default()
+                .split(',')
+                .map(|entity| entity.trim().to_lowercase())
+                .filter(|entity| !entity.is_empty())
+                .collect(),
+        })
+    }
+
+    pub fn allows(&self, entity_id: &str) -> bool {
+        self.allowed.iter().any(|allowed| match allowed.strip_suffix(".*") {
+            Some(domain) => entity_id.split_once('.').is_some_and(|(d, _)| d == domain),
+            None => allowed == entity_id,
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct EntityState {
+    pub entity_id: String,
+    pub state: String,
+    #[serde(default)]
+    pub attributes: serde_json::Value,
+}
+
+impl EntityState {
+    pub fn domain(&self) -> &str {
+        self.entity_id.split_once('.').map(|(domain, _)| domain).unwrap_or_default()
+    }
+
+    pub fn friendly_name(&self) -> String {
+        self.attributes["friendly_name"].as_str()
+            .map(str::to_string)
+            .unwrap_or_else(|| self.entity_id.clone())
+    }
+
+    /// "Living room lights: on", with the readings worth saying for thermostats and sensors.
+    pub fn spoken(&self) -> String {
+        let mut text = format!("{}: {}", self.friendly_name(), self.state);
+        if let Some(unit) = self.attributes["unit_of_measurement"].as_str() {
+            text.push_str(unit);
+        }
+        if let Some(current) = self.attributes["current_temperature"].as_f64() {
+            text.push_str(&format!(", currently {}", current));
+        }
+        if let Some(target) = self.attributes["temperature"].as_f64() {
+            text.push_str(&format!(", set to {}", target));
+        }
+        text
+    }
+}
+
+/// Services the mirror may call per domain; anything else (scripts, automations) stays off limits.
+fn allowed_services(domain: &str) -> &'static [&'static str] {
+    match domain {
+        "light" | "switch" | "fan" | "input_boolean" => &["turn_on", "turn_off", "toggle"],
+        "climate" => &["set_temperature", "set_hvac_mode", "turn_on", "turn_off"],
+        "cover" => &["open_cover", "close_cover", "stop_cover"],
+        "media_player" => &["media_play", "media_pause", "volume_set", "turn_on", "turn_off"],
+        "lock" => &["lock", "unlock"],
+        "scene" => &["turn_on"],
+        _ => &[],
+    }
+}
+
+fn client(config: &HaConfig, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    request.header("Authorization", format!("Bearer {}", config.token))
+}
+
+/// Every entity the allowlist lets through, with its current state.
+pub async fn allowed_states(config: &HaConfig) -> Result<Vec<EntityState>, HaError> {
+    let http = reqwest::Client::new();
+    let states: Vec<EntityState> = client(config, http.get(format!("{}/api/states", config.url)))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(states.into_iter().filter(|state| config.allows(&state.entity_id)).collect())
+}
+
+/// Finds an allowed entity by id or friendly name, e.g. "living room lights".
+pub async fn find_entity(config: &HaConfig, name: &str) -> Result<EntityState, HaError> {
+    let name = name.trim().to_lowercase();
+    let states = allowed_states(config).await?;
+    let found = states.iter()
+        .find(|state| state.entity_id == name || state.friendly_name().to_lowercase() == name)
+        .or_else(|| states.iter().find(|state| state.friendly_name().to_lowercase().contains(&name)));
+    match found {
+        Some(state) => Ok(state.clone()),
+        None => {
+            let names: Vec<String> = states.iter().map(|state| state.friendly_name()).collect();
+            Err(format!("No device called {}. Devices: {}", name, names.join(", ")).into())
+        }
+    }
+}
+
+/// Calls `service` on an allowed entity and returns its new state.
+pub async fn call_service(
+    config: &HaConfig,
+    entity: &EntityState,
+    service: &str,
+    data: &serde_json::Value
+) -> Result<EntityState, HaError> {
+    if !config.allows(&entity.entity_id) {
+        return Err(format!("{} is not allowed", entity.entity_id).into());
+    }
+    if !allowed_services(entity.domain()).contains(&service) {
+        return Err(format!("Can't {} {}", service.replace('_', " "), entity.friendly_name()).into());
+    }
+
+    let mut body = match data {
+        serde_json::Value::Object(data) => data.clone(),
+        _ => serde_json::Map::new(),
+    };
+    body.insert("entity_id".to_string(), serde_json::json!(entity.entity_id));
+
+    let http = reqwest::Client::new();
+    client(config, http.post(format!("{}/api/services/{}/{}", config.url, entity.domain(), service)))
+        .json(&body)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let state = client(config, http.get(format!("{}/api/states/{}", config.url, entity.entity_id)))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(state)
+}
+
+/// What the assistant hears back for `home_state`.
+pub async fn state_string(name: &str) -> String {
+    let Some(config) = HaConfig::from_env() else {
+        return "Home Assistant is not set up".to_string();
+    };
+    let result = if name.trim().is_empty() {
+        allowed_states(&config).await
+            .map(|states| states.iter().map(EntityState::spoken).collect::<Vec<_>>().join("\n"))
+    } else {
+        find_entity(&config, name).await.map(|state| state.spoken())
+    };
+    result.map_err(crate::logging::without_url).unwrap_or_else(|err| {
+        tracing::warn!("Home Assistant state failed: {}", err);
+        format!("Could not read the device: {}", err)
+    })
+}
+
+/// What the assistant hears back for `home_control`.
+pub async fn control_string(name: &str, service: &str, data: &serde_json::Value) -> String {
+    let Some(config) = HaConfig::from_env() else {
+        return "Home Assistant is not set up".to_string();
+    };
+    let result = match find_entity(&config, name).await {
+        Ok(entity) => call_service(&config, &entity, service, data).await,
+        Err(err) => Err(err),
+    };
+    match result.map_err(crate::logging::without_url) {
+        Ok(state) => format!("Done. {}", state.spoken()),
+        Err(err) => {
+            tracing::warn!("Home Assistant call failed: {}", err);
+            format!("Could not do that: {}", err)
+        }
+    }
+}
+
+pub async fn list_entities() -> Result<Json<Vec<EntityState>>, http::StatusCode> {
+    let config = HaConfig::from_env().ok_or(http::StatusCode::SERVICE_UNAVAILABLE)?;
+    match allowed_states(&config).await {
+        Ok(states) => Ok(Json(states)),
+        Err(err) => {
+            tracing::warn!("Failed to read Home Assistant states: {}", crate::logging::without_url(err));
+            Err(http::StatusCode::BAD_GATEWAY)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, extract::{Path, State}, routing::{get, post}};
+    use std::{collections::HashMap, net::{SocketAddr, TcpListener}, sync::{Arc, Mutex}};
+
+    const TOKEN: &str = "test-token";
+
+    type Devices = Arc<Mutex<HashMap<String, EntityState>>>;
+
+    fn authorized(headers: &http::HeaderMap) -> Result<(), http::StatusCode> {
+        let expected = format!("Bearer {}", TOKEN);
+        match headers.get(http::header::AUTHORIZATION) {
+            Some(value) if value == expected.as_str() => Ok(()),
+            _ => Err(http::StatusCode::UNAUTHORIZED),
+        }
+    }
+
+    async fn states(State(devices): State<Devices>, headers: http::HeaderMap) -> Result<Json<Vec<EntityState>>, http::StatusCode> {
+        authorized(&headers)?;
+        let mut states: Vec<EntityState> = devices.lock().unwrap().values().cloned().collect();
+        states.sort_by(|a, b| a.entity_id.cmp(&b.entity_id));
+        Ok(Json(states))
+    }
+
+    async fn state(
+        State(devices): State<Devices>,
+        Path(entity_id): Path<String>,
+        headers: http::HeaderMap
+    ) -> Result<Json<EntityState>, http::StatusCode> {
+        authorized(&headers)?;
+        devices.lock().unwrap().get(&entity_id).cloned().map(Json).ok_or(http::StatusCode::NOT_FOUND)
+    }
+
+    /// Switches lights like Home Assistant does, enough to see the call arrive.
+    async fn service(
+        State(devices): State<Devices>,
+        Path((_domain, service)): Path<(String, String)>,
+        headers: http::HeaderMap,
+        Json(body): Json<serde_json::Value>
+    ) -> Result<Json<Vec<EntityState>>, http::StatusCode> {
+        authorized(&headers)?;
+        let entity_id = body["entity_id"].as_str().ok_or(http::StatusCode::BAD_REQUEST)?;
+        let mut devices = devices.lock().unwrap();
+        let device = devices.get_mut(entity_id).ok_or(http::StatusCode::BAD_REQUEST)?;
+        device.state = match service.as_str() {
+            "turn_on" => "on".to_string(),
+            "turn_off" => "off".to_string(),
+            _ => return Err(http::StatusCode::BAD_REQUEST),
+        };
+        Ok(Json(vec![device.clone()]))
+    }
+
+    fn device(entity_id: &str, state: &str, name: &str) -> (String, EntityState) {
+        (entity_id.to_string(), EntityState {
+            entity_id: entity_id.to_string(),
+            state: state.to_string(),
+            attributes: serde_json::json!({ "friendly_name": name }),
+        })
+    }
+
+    /// A Home Assistant stand-in on a free local port, and a config pointing at it.
+    fn mock_home_assistant(token: &str) -> HaConfig {
+        let devices: Devices = Arc::new(Mutex::new(HashMap::from([
+            device("light.living_room", "off", "Living Room Lights"),
+            device("switch.kettle", "off", "Kettle"),
+            device("lock.front_door", "locked", "Front Door"),
+        ])));
+        let app = Router::new()
+            .route("/api/states", get(states))
+            .route("/api/states/:entity_id", get(state))
+            .route("/api/services/:domain/:service", post(service))
+            .with_state(devices);
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+        HaConfig {
+            url,
+            token: token.to_string(),
+            allowed: vec!["light.*".to_string(), "switch.kettle".to_string()],
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_a_state_by_friendly_name() {
+        let config = mock_home_assistant(TOKEN);
+        let state = find_entity(&config, "living room lights").await.unwrap();
+        assert_eq!(state.entity_id, "light.living_room");
+        assert_eq!(state.spoken(), "Living Room Lights: off");
+    }
+
+    #[tokio::test]
+    async fn leaves_out_entities_off_the_allowlist() {
+        let config = mock_home_assistant(TOKEN);
+        let ids: Vec<String> = allowed_states(&config).await.unwrap().into_iter().map(|state| state.entity_id).collect();
+        assert_eq!(ids, ["light.living_room", "switch.kettle"]);
+    }
+
+    #[tokio::test]
+    async fn calls_a_service_and_returns_the_new_state() {
+        let config = mock_home_assistant(TOKEN);
+        let kettle = find_entity(&config, "kettle").await.unwrap();
+        let state = call_service(&config, &kettle, "turn_on", &serde_json::json!({})).await.unwrap();
+        assert_eq!(state.state, "on");
+        assert_eq!(find_entity(&config, "switch.kettle").await.unwrap().state, "on");
+    }
+
+    #[tokio::test]
+    async fn refuses_services_the_domain_does_not_allow() {
+        let config = mock_home_assistant(TOKEN);
+        let lights = find_entity(&config, "living room lights").await.unwrap();
+        let err = call_service(&config, &lights, "unlock", &serde_json::json!({})).await.unwrap_err();
+        assert_eq!(err.to_string(), "Can't unlock Living Room Lights");
+    }
+
+    #[tokio::test]
+    async fn reports_a_bad_token() {
+        let config = mock_home_assistant("wrong-token");
+        let err = find_entity(&config, "kettle").await.unwrap_err();
+        let err = err.downcast::<reqwest::Error>().unwrap();
+        assert_eq!(err.status(), Some(reqwest::StatusCode::UNAUTHORIZED));
+    }
+
+    #[tokio::test]
+    async fn lists_devices_for_an_unknown_entity() {
+        let config = mock_home_assistant(TOKEN);
+        let err = find_entity(&config, "garage door").await.unwrap_err();
+        assert_eq!(err.to_string(), "No device called garage door. Devices: Living Room Lights, Kettle");
+        // Blocked devices can't be found either, even by id.
+        assert!(find_entity(&config, "lock.front_door").await.is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use axum::{Json, extract::{Path, State}};
+use chrono::{DateTime, Utc};
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, env, fs};
+
+use crate::announce::{self, Priority};
+use crate::push::{self, MirrorEvent};
+use crate::state::AppState;
+
+
+/// Where "remind me to ..." items go, timed ones are spoken when due.
+pub const REMINDERS_LIST: &str = "reminders";
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ListItem {
+    pub id: u64,
+    pub text: String,
+    #[serde(default)]
+    pub done: bool,
+    #[serde(default)]
+    pub due_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub announced: bool,
+}
+
+/// Named shared lists (groceries, packing, reminders), saved to `LISTS_PATH` after each change.
+#[derive(Deserialize, Serialize, Default)]
+pub struct ListStore {
+    next_id: u64,
+    lists: BTreeMap<String, Vec<ListItem>>,
+}
+
+fn lists_path() -> String {
+    dotenv().ok();
+    env::var("LISTS_PATH").unwrap_or_else(|_| "lists.json".to_string())
+}
+
+/// "Grocery list" and "Groceries " should land on the same list.
+pub fn list_name(name: &str) -> String {
+    let name = name.trim().to_lowercase();
+    let name = name.strip_suffix(" list").unwrap_or(&name).trim();
+    match name {
+        "grocery" | "shopping" => "groceries".to_string(),
+        "" => REMINDERS_LIST.to_string(),
+        name => name.to_string(),
+    }
+}
+
+impl ListStore {
+    pub fn load() -> ListStore {
+        let path = lists_path();
+        match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+                tracing::warn!("Error parsing lists in {}: {}", path, err);
+                ListStore::default()
+            }),
+            Err(_) => ListStore::default(),
+        }
+    }
+
+    fn save(&self) {
+        let result = serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::from)
+            .and_then(|contents| fs::write(lists_path(), contents));
+        if let Err(err) = result {
+            tracing::error!("Failed to save lists: {}", err);
+        }
+    }
+
+    pub fn all(&self) -> BTreeMap<String, Vec<ListItem>> {
+        self.lists.clone()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.lists.keys().cloned().collect()
+    }
+
+    pub fn items(&self, list: &str) -> Option<Vec<ListItem>> {
+        self.lists.get(&list_name(list)).cloned()
+    }
+
+    pub fn add(&mut self, list: &str, text: &str, due_at: Option<DateTime<Utc>>) -> ListItem {
+        self.next_id += 1;
+        let item = ListItem {
+            id: self.next_id,
+            text: text.trim().to_string(),
+            done: false,
+            due_at,
+            announced: false,
+        };
+        self.lists.entry(list_name(list)).or_default().push(item.clone());
+        self.save();
+        item
+    }
+
+    /// Finds an item by exact text first, then by a partial match.
+    fn position(&self, list: &str, item: &str) -> Option<(String, usize)> {
+        let list = list_name(list);
+        let items = self.lists.get(&list)?;
+        let item = item.trim().to_lowercase();
+        let position = items.iter().position(|i| i.text.to_lowercase() == item)
+            .or_else(|| items.iter().position(|i| i.text.to_lowercase().contains(&item)))?;
+        Some((list, position))
+    }
+
+    pub fn remove(&mut self, list: &str, item: &str) -> Option<ListItem> {
+        let (list, position) = self.position(list, item)?;
+        Some(self.remove_at(&list, position))
+    }
+
+    pub fn complete(&mut self, list: &str, item: &str) -> Option<ListItem> {
+        let (list, position) = self.position(list, item)?;
+        self.set_done(&list, position, true)
+    }
+
+    fn remove_at(&mut self, list: &str, position: usize) -> ListItem {
+        let items = self.lists.get_mut(list).expect("position came from this list");
+        let removed = items.remove(position);
+        if items.is_empty() {
+            self.lists.remove(list);
+        }
+        self.save();
+        removed
+    }
+
+    fn set_done(&mut self, list: &str, position: usize, done: bool) -> Option<ListItem> {
+        let item = self.lists.get_mut(list)?.get_mut(position)?;
+        item.done = done;
+        let item = item.clone();
+        self.save();
+        Some(item)
+    }
+
+    fn position_by_id(&self, list: &str, id: u64) -> Option<(String, usize)> {
+        let list = list_name(list);
+        let position = self.lists.get(&list)?.iter().position(|item| item.id == id)?;
+        Some((list, position))
+    }
+
+    /// Marks timed reminders that came due as announced and returns them.
+    fn take_due(&mut self, now: DateTime<Utc>) -> Vec<ListItem> {
+        let mut due = Vec::new();
+        for item in self.lists.values_mut().flatten() {
+            if !item.done && !item.announced && item.due_at.is_some_and(|at| at <= now) {
+                item.announced = true;
+                due.push(item.clone());
+            }
+        }
+        if !due.is_empty() {
+            self.save();
+        }
+        due
+    }
+}
+
+/// What the assistant reads back for a list.
+pub fn spoken_list(list: &str, items: &[ListItem]) -> String {
+    let open: Vec<&str> = items.iter().filter(|item| !item.done).map(|item| item.text.as_str()).collect();
+    let done = items.len() - open.len();
+    let mut text = match open.len() {
+        0 => format!("The {} list is empty", list),
+        _ => format!("{}: {}", list, open.join(", ")),
+    };
+    if done > 0 {
+        text.push_str(&format!(" ({} done)", done));
+    }
+    text
+}
+
+pub fn publish_lists(app_state: &AppState) {
+    let lists = app_state.lists.lock().unwrap().all();
+    push::publish(app_state, MirrorEvent::Lists { lists });
+}
+
+pub async fn run_scheduler(app_state: AppState) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(30));
+    loop {
+        interval.tick().await;
+        let due = app_state.lists.lock().unwrap().take_due(Utc::now());
+        if due.is_empty() {
+            continue;
+        }
+        publish_lists(&app_state);
+        for item in due {
+            let text = format!("Reminder: {}", item.text);
+            announce::enqueue(&app_state, &text, Priority::Normal);
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewItem {
+    pub text: String,
+    #[serde(default)]
+    pub due_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ItemUpdate {
+    pub done: bool,
+}
+
+pub async fn get_lists(State(app_state): State<AppState>) -> Json<BTreeMap<String, Vec<ListItem>>> {
+    Json(app_state.lists.lock().unwrap().all())
+}
+
+pub async fn get_list(
+    State(app_state): State<AppState>,
+    Path(list): Path<String>
+) -> Result<Json<Vec<ListItem>>, http::StatusCode> {
+    app_state.lists.lock().unwrap().items(&list)
+        .map(Json)
+        .ok_or(http::StatusCode::NOT_FOUND)
+}
+
+pub async fn add_item(
+    State(app_state): State<AppState>,
+    Path(list): Path<String>,
+    Json(payload): Json<NewItem>
+) -> Result<Json<ListItem>, http::StatusCode> {
+    if payload.text.trim().is_empty() {
+        return Err(http::StatusCode::BAD_REQUEST);
+    }
+    let item = app_state.lists.lock().unwrap().add(&list, &payload.text, payload.due_at);
+    publish_lists(&app_state);
+    Ok(Json(item))
+}
+
+pub async fn update_item(
+    State(app_state): State<AppState>,
+    Path((list, id)): Path<(String, u64)>,
+    Json(payload): Json<ItemUpdate>
+) -> Result<Json<ListItem>, http::StatusCode> {
+    let item = {
+        let mut lists = app_state.lists.lock().unwrap();
+        let (list, position) = lists.position_by_id(&list, id).ok_or(http::StatusCode::NOT_FOUND)?;
+        lists.set_done(&list, position, payload.done).ok_or(http::StatusCode::NOT_FOUND)?
+    };
+    publish_lists(&app_state);
+    Ok(Json(item))
+}
+
+pub async fn delete_item(
+    State(app_state): State<AppState>,
+    Path((list, id)): Path<(String, u64)>
+) -> Result<Json<ListItem>, http::StatusCode> {
+    let item = {
+        let mut lists = app_state.lists.lock().unwrap();
+        let (list, position) = lists.position_by_id(&list, id).ok_or(http::StatusCode::NOT_FOUND)?;
+        lists.remove_at(&list, position)
+    };
+    publish_lists(&app_state);
+    Ok(Json(item))
+}
@@ -0,0 +1,76 @@
+use axum::{middleware::Next, response::Response};
+use dotenv::dotenv;
+use std::{env, sync::OnceLock, time::Instant};
+use tracing::Instrument;
+use tracing_subscriber::{EnvFilter, fmt::format::FmtSpan};
+
+
+static LOG_CONTENT: OnceLock<bool> = OnceLock::new();
+
+/// Sets up logging from the environment:
+/// - `LOG_LEVEL` (or `RUST_LOG`) filters, e.g. `info` or `smart_mirror_v2=debug,info`
+/// - `LOG_FORMAT` is `json` (default) for log stores, or `text` for reading in a terminal
+/// - `LOG_SPAN_TIMINGS=false` stops the timing line written when each span closes
+/// - `LOG_CONTENT=true` logs what people said and what the assistant answered, otherwise it is redacted
+pub fn init() {
+    dotenv().ok();
+    let filter = env::var("LOG_LEVEL").ok()
+        .or_else(|| env::var("RUST_LOG").ok())
+        .unwrap_or_else(|| "info".to_string());
+    let span_events = match env::var("LOG_SPAN_TIMINGS").as_deref() {
+        Ok("false") | Ok("0") => FmtSpan::NONE,
+        _ => FmtSpan::CLOSE,
+    };
+    LOG_CONTENT.get_or_init(|| matches!(env::var("LOG_CONTENT").as_deref(), Ok("true") | Ok("1")));
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new(filter))
+        .with_span_events(span_events);
+    match env::var("LOG_FORMAT").as_deref() {
+        Ok("text") => builder.init(),
+        _ => builder.json().with_current_span(true).with_span_list(false).init(),
+    }
+}
+
+/// Message content for a log line: the text itself only with `LOG_CONTENT=true`.
+pub fn content(text: &str) -> String {
+    if *LOG_CONTENT.get().unwrap_or(&false) {
+        text.to_string()
+    } else {
+        format!("[{} chars redacted]", text.chars().count())
+    }
+}
+
+/// reqwest errors print the request URL, query string and any API key in it included.
+/// Boxed errors get the same treatment when one is underneath.
+pub fn without_url(err: Box<dyn std::error::Error + Send + Sync>) -> Box<dyn std::error::Error + Send + Sync> {
+    match err.downcast::<reqwest::Error>() {
+        Ok(err) => Box::new(err.without_url()),
+        Err(err) => err,
+    }
+}
+
+/// Middleware: one span per request with its method, path, status and time taken.
+/// The query string is left out, it can carry `?token=`.
+pub async fn trace_requests<B>(request: http::Request<B>, next: Next<B>) -> Response {
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = %request.uri().path(),
+        status = tracing::field::Empty,
+        millis = tracing::field::Empty,
+    );
+    let started = Instant::now();
+    async move {
+        let response = next.run(request).await;
+        let span = tracing::Span::current();
+        span.record("status", response.status().as_u16());
+        span.record("millis", started.elapsed().as_millis() as u64);
+        if response.status().is_server_error() {
+            tracing::warn!(status = response.status().as_u16(), "Request failed");
+        }
+        response
+    }
+    .instrument(span)
+    .await
+}
@@ -2,53 +2,139 @@ mod weather_api;
 mod calendar_api;
 mod chat;
 mod text_to_speech;
+mod event_reminders;
+mod push;
+mod speech_to_text;
+mod wake_word;
+mod tts_cache;
+mod voices;
+mod profiles;
+mod places;
+mod timers;
+mod lists;
+mod briefing;
+mod news;
+mod commute;
+mod home_assistant;
+mod mqtt;
+mod announce;
+mod auth;
+mod usage;
+mod guard;
+mod logging;
 
-use axum::{Router, routing::{get, post}, http::Method};
-use tower_http::cors::{CorsLayer, AllowMethods, AllowHeaders, Any};
+use axum::{Router, routing::{delete, get, patch, post}, http::Method};
+use tower_http::{cors::{CorsLayer, AllowMethods, AllowHeaders, AllowOrigin}, services::ServeDir};
 
-use std::{sync::{Arc, Mutex}};
+use std::{collections::HashMap, sync::{Arc, Mutex}};
 
 mod state;
 use state::AppState;
 
 #[tokio::main]
 async fn main() {
-    
-    
+    dotenv::dotenv().ok();
+    logging::init();
+
     let app_state = AppState {
-        messages: Arc::new(Mutex::new(Vec::<serde_json::Value>::new())),
+        messages: Arc::new(Mutex::new(HashMap::new())),
+        reminders: Arc::new(Mutex::new(event_reminders::ReminderState::default())),
+        events: push::channel(),
+        clips: Arc::new(Mutex::new(text_to_speech::ClipStore::default())),
+        display_location: Arc::new(Mutex::new(None)),
+        session_voice: Arc::new(Mutex::new(HashMap::new())),
+        timers: Arc::new(Mutex::new(timers::TimerStore::load())),
+        lists: Arc::new(Mutex::new(lists::ListStore::load())),
+        news: Arc::new(Mutex::new(Vec::new())),
+        announcements: Arc::new(Mutex::new(announce::AnnounceQueue::default())),
+        api_keys: Arc::new(Mutex::new(auth::KeyStore::load())),
+        usage: Arc::new(Mutex::new(usage::UsageTracker::load())),
+        guard: Arc::new(Mutex::new(guard::GuardState::default())),
+        active_user: Arc::new(Mutex::new(None)),
     };
-    {
-        let message_to_add = chat::to_json_message("sys", "sys");
-        let curr_date = format!("Current Date:{}", chrono::prelude::Local::now());
-        let curr_date_system = chat::to_json_message("system", curr_date.as_str());
-        let mut messages_lock = app_state.messages.lock().unwrap();
-        messages_lock.push(message_to_add);
-        messages_lock.push(curr_date_system);
-    }
+    tokio::spawn(event_reminders::run_scheduler(app_state.clone()));
+    tokio::spawn(timers::run_scheduler(app_state.clone()));
+    tokio::spawn(lists::run_scheduler(app_state.clone()));
+    tokio::spawn(briefing::run_scheduler(app_state.clone()));
+    tokio::spawn(news::run_refresh(app_state.clone()));
+    tokio::spawn(commute::run_departures(app_state.clone()));
+    tokio::spawn(mqtt::run_bridge(app_state.clone()));
+    tokio::spawn(announce::run_queue(app_state.clone()));
+    tokio::spawn(push::run_refresh(app_state.clone()));
+    tokio::spawn(tts_cache::prewarm(app_state.clone()));
+
+    // The mirror UI is served from here, so only extra origins (dev servers, phone apps) need CORS.
     let cors_layer = CorsLayer::new()
-        .allow_methods(AllowMethods::list(vec![Method::GET, Method::POST, Method::OPTIONS]))
+        .allow_methods(AllowMethods::list(vec![Method::GET, Method::POST, Method::PATCH, Method::DELETE, Method::OPTIONS]))
         .allow_headers(AllowHeaders::list(vec![
-            http::header::HeaderName::from_static("content-type")
+            http::header::HeaderName::from_static("content-type"),
+            http::header::HeaderName::from_static("authorization"),
+            http::header::HeaderName::from_static("x-mirror-user")
         ]))
-        .allow_origin(Any);
+        .allow_origin(AllowOrigin::list(allowed_origins()));
+
+    let frontend_dir = std::env::var("FRONTEND_DIR").unwrap_or_else(|_| "mirror_frontend".to_string());
 
     let router = Router::new()
         .route("/weather", get(weather_api::get_weather_json))
         .route("/calendar", get(calendar_api::get_calendar_json)) 
         .route("/chat", post(text_to_speech::return_audio))
+        .route("/chat/text", post(chat::chat_text))
+        .route("/chat/audio", post(speech_to_text::chat_audio))
+        .route("/transcribe", post(speech_to_text::transcribe_json))
+        .route("/listen", get(wake_word::listen))
+        .route("/clips/:clip_id", get(text_to_speech::get_clip))
+        .route("/events", get(push::event_stream))
+        .route("/voices", get(voices::list_voices))
+        .route("/timers", get(timers::list_timers))
+        .route("/timers/:id", delete(timers::cancel_timer))
+        .route("/timers/:id/stop", post(timers::stop_timer))
+        .route("/lists", get(lists::get_lists))
+        .route("/lists/:list", get(lists::get_list).post(lists::add_item))
+        .route("/lists/:list/:id", patch(lists::update_item).delete(lists::delete_item))
+        .route("/announce", post(announce::post_announce))
+        .route("/home", get(home_assistant::list_entities))
+        .route("/commute", get(commute::get_commute))
+        .route("/news", get(news::get_news))
+        .route("/briefing", post(briefing::play_briefing))
+        .route("/geocode", get(weather_api::geocode_json))
+        .route("/places", get(places::list_places))
+        .route("/profiles", get(profiles::list_profiles))
+        .route("/profiles/active", get(profiles::get_active_profile).post(profiles::set_active_profile))
+        .route("/keys", get(auth::list_keys).post(auth::create_key))
+        .route("/keys/:name", delete(auth::revoke_key))
+        .route("/usage", get(usage::get_usage))
+        .fallback_service(ServeDir::new(frontend_dir))
+        .layer(axum::middleware::from_fn_with_state(app_state.clone(), usage::rate_limit))
+        .layer(axum::middleware::from_fn_with_state(app_state.clone(), auth::require_key))
+        .layer(axum::middleware::from_fn(logging::trace_requests))
         .layer(cors_layer)
         .with_state(app_state.clone()); 
 
-    let addr = std::net::SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)), 3000);
+    let addr: std::net::SocketAddr = std::env::var("BIND_ADDR")
+        .unwrap_or_else(|_| "127.0.0.1:3000".to_string())
+        .parse()
+        .expect("BIND_ADDR must look like 127.0.0.1:3000");
+    tracing::info!("Mirror running at http://{}", addr);
 
     axum::Server::bind(&addr)
-        .serve(router.into_make_service())
+        .serve(router.into_make_service_with_connect_info::<std::net::SocketAddr>())
         .await
         .unwrap();
 }
 
-
-
-
-
+fn allowed_origins() -> Vec<http::HeaderValue> {
+    std::env::var("ALLOWED_ORIGINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|origin| origin.trim())
+        .filter(|origin| !origin.is_empty())
+        .filter_map(|origin| match origin.parse() {
+            Ok(value) => Some(value),
+            Err(_) => {
+                tracing::warn!("Ignoring invalid origin in ALLOWED_ORIGINS: {}", origin);
+                None
+            }
+        })
+        .collect()
+}
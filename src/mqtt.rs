@@ -0,0 +1,162 @@
+use axum::Json;
+use dotenv::dotenv;
+use rumqttc::{AsyncClient, Event, Incoming, MqttOptions, QoS};
+use serde::Deserialize;
+use std::{env, time::Duration};
+
+use crate::announce::{self, Announcement, Priority};
+use crate::chat::{self, ChatRequest};
+use crate::push::{self, MirrorEvent};
+use crate::state::AppState;
+use crate::usage;
+
+
+/// Broker settings; `MQTT_HOST` unset leaves the bridge off.
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub credentials: Option<(String, String)>,
+    /// Everything lives under this, e.g. `mirror/events/timers` and `mirror/command/say`.
+    pub prefix: String,
+}
+
+impl MqttConfig {
+    pub fn from_env() -> Option<MqttConfig> {
+        dotenv().ok();
+        Some(MqttConfig {
+            host: env::var("MQTT_HOST").ok()?,
+            port: env::var("MQTT_PORT").ok().and_then(|p| p.parse().ok()).unwrap_or(1883),
+            client_id: env::var("MQTT_CLIENT_ID").unwrap_or_else(|_| "smart-mirror".to_string()),
+            credentials: env::var("MQTT_USERNAME").ok().map(|user| (user, env::var("MQTT_PASSWORD").unwrap_or_default())),
+            prefix: env::var("MQTT_TOPIC_PREFIX").unwrap_or_else(|_| "mirror".to_string()).trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+/// `{prefix}/command/chat` accepts this or a plain text message.
+#[derive(Deserialize, Debug)]
+struct ChatCommand {
+    message: String,
+    #[serde(default)]
+    user: Option<String>,
+}
+
+/// Publishes every display event as JSON on `{prefix}/events/{type}`.
+async fn forward_events(app_state: AppState, client: AsyncClient, prefix: String) {
+    let mut events = app_state.events.subscribe();
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("MQTT bridge skipped {} events", skipped);
+                continue;
+            },
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+        };
+        let payload = match serde_json::to_value(&event) {
+            Ok(payload) => payload,
+            Err(err) => {
+                tracing::warn!("Failed to encode event for MQTT: {}", err);
+                continue;
+            }
+        };
+        let kind = payload["type"].as_str().unwrap_or("event").to_string();
+        let topic = format!("{}/events/{}", prefix, kind);
+        if let Err(err) = client.publish(topic, QoS::AtLeastOnce, false, payload.to_string()).await {
+            tracing::warn!("Failed to publish to MQTT: {}", err);
+        }
+    }
+}
+
+/// Client name the rate limits count MQTT under, the broker doesn't say who sent a message.
+const MQTT_CLIENT: &str = "mqtt";
+
+/// Chat and say reach paid APIs, so they go through the same limits as their HTTP routes.
+fn within_limits(app_state: &AppState, command: &str) -> bool {
+    if let Err(retry_after) = usage::check_rate(app_state, MQTT_CLIENT) {
+        tracing::warn!("Dropping MQTT {}, rate limited for {} more seconds", command, retry_after.as_secs().max(1));
+        return false;
+    }
+    true
+}
+
+async fn handle_command(app_state: AppState, command: String, payload: String) {
+    match command.as_str() {
+        "chat" => {
+            let request = serde_json::from_str::<ChatCommand>(&payload)
+                .unwrap_or(ChatCommand { message: payload, user: None });
+            if request.message.trim().is_empty() || !within_limits(&app_state, "chat") {
+                return;
+            }
+            if !usage::chat_allowed(&app_state) {
+                announce::enqueue(&app_state, chat::BUDGET_MSG, Priority::Normal);
+                return;
+            }
+            let Json(reply) = chat::handle_chat_request(
+                app_state.clone(),
+                Json(ChatRequest { message: request.message, user: request.user, voice: None })
+            ).await;
+            announce::enqueue(&app_state, &reply.text, Priority::Normal);
+        },
+        "say" => {
+            let announcement = serde_json::from_str::<Announcement>(&payload)
+                .unwrap_or(Announcement { text: payload, voice: None, priority: Priority::Normal });
+            if !announcement.text.trim().is_empty() && within_limits(&app_state, "say") {
+                app_state.announcements.lock().unwrap().push(announcement);
+            }
+        },
+        "notify" => push::publish(&app_state, MirrorEvent::Alert { text: payload }),
+        "refresh" => {
+            push::publish_weather(&app_state).await;
+            if env::var("GOOGLE_CREDENTIALS_PATH").is_ok() {
+                push::publish_calendar(app_state.clone()).await;
+            }
+            crate::timers::publish_timers(&app_state);
+            crate::lists::publish_lists(&app_state);
+        },
+        other => tracing::warn!("Unknown MQTT command: {}", other),
+    }
+}
+
+/// Connects to the broker, mirrors events out and takes commands in on `{prefix}/command/{chat|say|notify|refresh}`.
+pub async fn run_bridge(app_state: AppState) {
+    let Some(config) = MqttConfig::from_env() else {
+        return;
+    };
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if let Some((username, password)) = &config.credentials {
+        options.set_credentials(username, password);
+    }
+
+    let (client, mut eventloop) = AsyncClient::new(options, 64);
+    tokio::spawn(forward_events(app_state.clone(), client.clone(), config.prefix.clone()));
+
+    let command_prefix = format!("{}/command/", config.prefix);
+    loop {
+        match eventloop.poll().await {
+            // Subscriptions don't survive a reconnect with a clean session, so renew them each time.
+            // try_subscribe, since awaiting the request queue here would wait on this very loop.
+            Ok(Event::Incoming(Incoming::ConnAck(_))) => {
+                tracing::info!("MQTT connected to {}:{}", config.host, config.port);
+                if let Err(err) = client.try_subscribe(format!("{}#", command_prefix), QoS::AtLeastOnce) {
+                    tracing::warn!("Failed to subscribe to MQTT commands: {}", err);
+                }
+            },
+            Ok(Event::Incoming(Incoming::Publish(publish))) => {
+                let Some(command) = publish.topic.strip_prefix(&command_prefix) else {
+                    continue;
+                };
+                let payload = String::from_utf8_lossy(&publish.payload).to_string();
+                // Chat can take seconds, keep the connection serviced meanwhile.
+                tokio::spawn(handle_command(app_state.clone(), command.to_string(), payload));
+            },
+            Ok(_) => {},
+            Err(err) => {
+                tracing::warn!("MQTT connection error: {}", err);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use axum::{Json, extract::{Query, State}};
+use chrono::{DateTime, Utc};
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, env};
+
+use crate::push::{self, MirrorEvent};
+use crate::state::AppState;
+
+
+type NewsError = Box<dyn std::error::Error + Send + Sync>;
+
+/// How many headlines displays get pushed.
+const PUSHED_HEADLINES: usize = 10;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Headline {
+    pub title: String,
+    pub link: Option<String>,
+    pub source: String,
+    pub topic: String,
+    pub published: Option<DateTime<Utc>>,
+    pub summary: Option<String>,
+}
+
+/// One configured feed: a URL or a local file, filed under a topic.
+#[derive(Debug, Clone)]
+pub struct FeedSource {
+    pub topic: String,
+    pub location: String,
+}
+
+/// Reads `NEWS_FEEDS`, comma separated `topic=url` entries. A bare url is filed under "general".
+pub fn feed_sources() -> Vec<FeedSource> {
+    dotenv().ok();
+    env::var("NEWS_FEEDS").unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once('=') {
+            // Only a plain word before '=' is a topic, query strings in urls have them too.
+            Some((topic, location)) if !topic.contains(['/', ':', '?', '.']) => FeedSource {
+                topic: topic.trim().to_lowercase(),
+                location: location.trim().to_string(),
+            },
+            _ => FeedSource { topic: "general".to_string(), location: entry.to_string() },
+        })
+        .collect()
+}
+
+fn max_items() -> usize {
+    dotenv().ok();
+    env::var("NEWS_MAX_ITEMS").ok().and_then(|n| n.parse().ok()).unwrap_or(50)
+}
+
+/// Feed summaries are often HTML, only the text is worth reading out.
+fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            },
+            _ if !in_tag => text.push(c),
+            _ => {},
+        }
+    }
+    text.replace("&amp;", "&")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", " ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn parse_feed(source: &FeedSource, bytes: &[u8]) -> Result<Vec<Headline>, NewsError> {
+    let feed = feed_rs::parser::parse(bytes)?;
+    let source_name = feed.title.map(|title| title.content).unwrap_or_else(|| source.location.clone());
+    Ok(feed.entries.into_iter().filter_map(|entry| {
+        let title = strip_html(&entry.title?.content);
+        if title.is_empty() {
+            return None;
+        }
+        Some(Headline {
+            title,
+            link: entry.links.first().map(|link| link.href.clone()),
+            source: source_name.clone(),
+            topic: source.topic.clone(),
+            published: entry.published.or(entry.updated),
+            summary: entry.summary.map(|summary| strip_html(&summary.content)).filter(|summary| !summary.is_empty()),
+        })
+    }).collect())
+}
+
+async fn fetch_feed(source: &FeedSource) -> Result<Vec<Headline>, NewsError> {
+    let bytes = if source.location.starts_with("http://") || source.location.starts_with("https://") {
+        reqwest::Client::new().get(&source.location).send().await?.error_for_status()?.bytes().await?.to_vec()
+    } else {
+        // Local files make it easy to try feeds out, or to point at fixtures.
+        let path = source.location.strip_prefix("file://").unwrap_or(&source.location);
+        tokio::fs::read(path).await?
+    };
+    parse_feed(source, &bytes)
+}
+
+fn dedup_key(headline: &Headline) -> String {
+    match &headline.link {
+        Some(link) => link.trim_end_matches('/').to_lowercase(),
+        None => headline.title.to_lowercase().chars().filter(|c| c.is_alphanumeric()).collect(),
+    }
+}
+
+/// Newest first, each story once even when several feeds carry it.
+pub fn merge(mut headlines: Vec<Headline>, limit: usize) -> Vec<Headline> {
+    headlines.sort_by_key(|headline| std::cmp::Reverse(headline.published));
+    let mut seen_links = HashSet::new();
+    let mut seen_titles = HashSet::new();
+    headlines.retain(|headline| {
+        let title: String = headline.title.to_lowercase().chars().filter(|c| c.is_alphanumeric()).collect();
+        let fresh_link = seen_links.insert(dedup_key(headline));
+        let fresh_title = seen_titles.insert(title);
+        fresh_link && fresh_title
+    });
+    headlines.truncate(limit);
+    headlines
+}
+
+pub async fn fetch_all() -> Vec<Headline> {
+    let mut headlines = Vec::new();
+    for source in feed_sources() {
+        match fetch_feed(&source).await {
+            Ok(items) => headlines.extend(items),
+            // Feed URLs can carry tokens in their query, so neither goes in the log.
+            Err(err) => tracing::warn!(
+                "Failed to read feed {}: {}",
+                source.location.split('?').next().unwrap_or_default(),
+                crate::logging::without_url(err)
+            ),
+        }
+    }
+    merge(headlines, max_items())
+}
+
+/// The stored headlines, fetching them first if nothing has been polled yet.
+pub async fn headlines(app_state: &AppState, topic: Option<&str>) -> Vec<Headline> {
+    let empty = app_state.news.lock().unwrap().is_empty();
+    if empty {
+        let fetched = fetch_all().await;
+        *app_state.news.lock().unwrap() = fetched;
+    }
+    let topic = topic.map(str::trim).filter(|topic| !topic.is_empty()).map(str::to_lowercase);
+    app_state.news.lock().unwrap()
+        .iter()
+        .filter(|headline| topic.as_ref().is_none_or(|topic| &headline.topic == topic))
+        .cloned()
+        .collect()
+}
+
+pub fn topics() -> Vec<String> {
+    let mut topics: Vec<String> = feed_sources().into_iter().map(|source| source.topic).collect();
+    topics.sort();
+    topics.dedup();
+    topics
+}
+
+/// Polls the feeds every `NEWS_REFRESH_MINUTES` and pushes the top stories when they change.
+/// With 0 the feeds are only read when someone asks for news.
+pub async fn run_refresh(app_state: AppState) {
+    dotenv().ok();
+    if feed_sources().is_empty() {
+        return;
+    }
+    let minutes: u64 = env::var("NEWS_REFRESH_MINUTES").ok()
+        .and_then(|m| m.parse().ok())
+        .unwrap_or(30);
+    if minutes == 0 {
+        return;
+    }
+
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(minutes * 60));
+    loop {
+        interval.tick().await;
+        let fetched = fetch_all().await;
+        if fetched.is_empty() {
+            continue;
+        }
+        let top: Vec<Headline> = fetched.iter().take(PUSHED_HEADLINES).cloned().collect();
+        let changed = {
+            let mut news = app_state.news.lock().unwrap();
+            let changed = news.iter().take(PUSHED_HEADLINES).map(dedup_key).ne(top.iter().map(dedup_key));
+            *news = fetched;
+            changed
+        };
+        if changed {
+            push::publish(&app_state, MirrorEvent::News { headlines: top });
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewsQuery {
+    pub topic: Option<String>,
+    pub limit: Option<usize>,
+}
+
+pub async fn get_news(State(app_state): State<AppState>, Query(query): Query<NewsQuery>) -> Json<Vec<Headline>> {
+    let mut headlines = headlines(&app_state, query.topic.as_deref()).await;
+    headlines.truncate(query.limit.unwrap_or(PUSHED_HEADLINES));
+    Json(headlines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(topic: &str, location: &str) -> FeedSource {
+        FeedSource { topic: topic.to_string(), location: location.to_string() }
+    }
+
+    fn rss() -> Vec<Headline> {
+        parse_feed(&source("local", "tests/fixtures/news.rss"), include_bytes!("../tests/fixtures/news.rss")).unwrap()
+    }
+
+    fn atom() -> Vec<Headline> {
+        parse_feed(&source("tech", "tests/fixtures/news.atom"), include_bytes!("../tests/fixtures/news.atom")).unwrap()
+    }
+
+    fn at(time: &str) -> Option<DateTime<Utc>> {
+        Some(time.parse().unwrap())
+    }
+
+    #[test]
+    fn parses_rss() {
+        let headlines = rss();
+        let titles: Vec<&str> = headlines.iter().map(|h| h.title.as_str()).collect();
+        assert_eq!(titles, ["Bridge reopens after repairs", "Library extends opening hours"]);
+        let bridge = &headlines[0];
+        assert_eq!(bridge.source, "Local Paper");
+        assert_eq!(bridge.topic, "local");
+        assert_eq!(bridge.link.as_deref(), Some("https://paper.example/bridge"));
+        assert_eq!(bridge.published, at("2025-03-03T08:00:00Z"));
+        assert_eq!(bridge.summary.as_deref(), Some("Traffic & buses are back on the river bridge ."));
+        assert_eq!(headlines[1].summary, None);
+    }
+
+    #[test]
+    fn parses_atom() {
+        let headlines = atom();
+        assert_eq!(headlines.len(), 2);
+        let battery = &headlines[0];
+        assert_eq!(battery.title, "New battery lasts a week");
+        assert_eq!(battery.source, "Tech Wire");
+        assert_eq!(battery.topic, "tech");
+        assert_eq!(battery.link.as_deref(), Some("https://wire.example/battery"));
+        // Without a published date the update time stands in.
+        assert_eq!(battery.published, at("2025-03-03T09:00:00Z"));
+        assert_eq!(battery.summary.as_deref(), Some("Lab results look promising."));
+        assert_eq!(headlines[1].published, at("2025-03-03T07:00:00Z"));
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(parse_feed(&source("general", "nowhere"), b"not a feed").is_err());
+    }
+
+    #[test]
+    fn merge_puts_newest_first_and_drops_repeats() {
+        let mut headlines = rss();
+        headlines.extend(atom());
+        let mut repeat = headlines[1].clone();
+        repeat.link = Some("https://paper.example/library/".to_string());
+        headlines.push(repeat);
+
+        let merged = merge(headlines, 10);
+        let titles: Vec<&str> = merged.iter().map(|h| h.title.as_str()).collect();
+        // The wire's bridge story matches the paper's title once punctuation and case are gone,
+        // the repeated library link matches despite its trailing slash.
+        assert_eq!(titles, ["New battery lasts a week", "Bridge reopens after repairs", "Library extends opening hours"]);
+    }
+
+    #[test]
+    fn merge_keeps_undated_last_and_truncates() {
+        let mut headlines = atom();
+        headlines.push(Headline {
+            title: "Undated".to_string(),
+            link: None,
+            source: "Somewhere".to_string(),
+            topic: "general".to_string(),
+            published: None,
+            summary: None,
+        });
+        let merged = merge(headlines.clone(), 10);
+        assert_eq!(merged.last().unwrap().title, "Undated");
+        assert_eq!(merge(headlines, 1).len(), 1);
+    }
+}
@@ -0,0 +1,94 @@
+use axum::Json;
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone, Utc};
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, env, fs};
+
+
+/// A spot the household refers to by name, e.g. "home", "work" or "grandma's".
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Place {
+    pub lat: f64,
+    pub lon: f64,
+    /// IANA name such as America/Los_Angeles.
+    #[serde(default)]
+    pub timezone: Option<String>,
+}
+
+impl Place {
+    /// What weatherapi.com expects for `q`.
+    pub fn query(&self) -> String {
+        format!("{},{}", self.lat, self.lon)
+    }
+}
+
+/// Reads `PLACES_PATH` (default places.json), keyed by name. `HOME_LAT`/`HOME_LON` add or replace "home".
+pub fn load_places() -> HashMap<String, Place> {
+    dotenv().ok();
+    let path = env::var("PLACES_PATH").unwrap_or_else(|_| "places.json".to_string());
+    let mut places: HashMap<String, Place> = match fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+            tracing::warn!("Error parsing places in {}: {}", path, err);
+            HashMap::new()
+        }),
+        Err(_) => HashMap::new(),
+    };
+
+    let lat = env::var("HOME_LAT").ok().and_then(|lat| lat.parse().ok());
+    let lon = env::var("HOME_LON").ok().and_then(|lon| lon.parse().ok());
+    if let (Some(lat), Some(lon)) = (lat, lon) {
+        places.insert("home".to_string(), Place {
+            lat,
+            lon,
+            timezone: env::var("HOME_TIMEZONE").ok(),
+        });
+    }
+    places
+}
+
+/// Looks a saved place up by name, ignoring case and a leading "my".
+pub fn find(name: &str) -> Option<Place> {
+    let name = name.trim();
+    let name = name.strip_prefix("my ").unwrap_or(name);
+    load_places().into_iter()
+        .find(|(place, _)| place.eq_ignore_ascii_case(name))
+        .map(|(_, place)| place)
+}
+
+pub fn home() -> Option<Place> {
+    find("home")
+}
+
+/// Turns a place name into coordinates; anything else is passed to the weather API as typed.
+pub fn to_query(location: &str) -> String {
+    find(location)
+        .map(|place| place.query())
+        .unwrap_or_else(|| location.to_string())
+}
+
+pub fn home_timezone() -> Option<chrono_tz::Tz> {
+    home()
+        .and_then(|place| place.timezone)
+        .and_then(|name| name.parse::<chrono_tz::Tz>().map_err(|err| tracing::warn!("Invalid home timezone {}: {}", name, err)).ok())
+}
+
+/// The current time at home, or the server's local time when no home timezone is set.
+pub fn home_now() -> DateTime<FixedOffset> {
+    let now = Local::now();
+    match home_timezone() {
+        Some(timezone) => now.with_timezone(&timezone).fixed_offset(),
+        None => now.fixed_offset(),
+    }
+}
+
+/// Reads a wall clock time as home time.
+pub fn from_home_time(naive: NaiveDateTime) -> Option<DateTime<Utc>> {
+    match home_timezone() {
+        Some(timezone) => timezone.from_local_datetime(&naive).earliest().map(|time| time.with_timezone(&Utc)),
+        None => Local.from_local_datetime(&naive).earliest().map(|time| time.with_timezone(&Utc)),
+    }
+}
+
+pub async fn list_places() -> Json<HashMap<String, Place>> {
+    Json(load_places())
+}
@@ -0,0 +1,154 @@
+use axum::{Json, extract::State};
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use std::{env, fs};
+
+use crate::push::{self, MirrorEvent};
+use crate::state::AppState;
+
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    #[default]
+    Imperial,
+    Metric,
+}
+
+/// One member of the household and how the mirror should treat them.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct UserProfile {
+    pub id: String,
+    pub name: String,
+    /// Other names the person may introduce themselves with.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Calendar ids to read, all calendars when empty.
+    #[serde(default)]
+    pub calendars: Vec<String>,
+    /// Where new events go, `GOOGLE_CALENDAR_ID` when unset.
+    #[serde(default)]
+    pub primary_calendar: Option<String>,
+    #[serde(default)]
+    pub location: Option<String>,
+    #[serde(default)]
+    pub units: Units,
+    #[serde(default)]
+    pub voice: Option<String>,
+    /// Facts the person asked the mirror to remember.
+    #[serde(default)]
+    pub memory: Vec<String>,
+}
+
+impl UserProfile {
+    fn household() -> UserProfile {
+        UserProfile {
+            id: "household".to_string(),
+            name: "Household".to_string(),
+            aliases: Vec::new(),
+            calendars: Vec::new(),
+            primary_calendar: None,
+            location: None,
+            units: Units::default(),
+            voice: None,
+            memory: Vec::new(),
+        }
+    }
+
+    fn answers_to(&self, name: &str) -> bool {
+        let name = name.trim();
+        self.id.eq_ignore_ascii_case(name)
+            || self.name.eq_ignore_ascii_case(name)
+            || self.aliases.iter().any(|alias| alias.eq_ignore_ascii_case(name))
+    }
+}
+
+fn profiles_path() -> String {
+    dotenv().ok();
+    env::var("PROFILES_PATH").unwrap_or_else(|_| "profiles.json".to_string())
+}
+
+/// Reads `PROFILES_PATH` (default profiles.json). Without it everyone shares one "household" profile.
+pub fn load_profiles() -> Vec<UserProfile> {
+    let path = profiles_path();
+    let profiles: Vec<UserProfile> = match fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+            tracing::warn!("Error parsing profiles in {}: {}", path, err);
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    };
+    if profiles.is_empty() {
+        return vec![UserProfile::household()];
+    }
+    profiles
+}
+
+pub fn save_profiles(profiles: &[UserProfile]) -> std::io::Result<()> {
+    let contents = serde_json::to_string_pretty(profiles)?;
+    fs::write(profiles_path(), contents)
+}
+
+pub fn find(name: &str) -> Option<UserProfile> {
+    load_profiles().into_iter().find(|profile| profile.answers_to(name))
+}
+
+/// The profile a request acts for: the one it names, else whoever is active, else the first.
+pub fn resolve(app_state: &AppState, requested: Option<&str>) -> UserProfile {
+    let active = app_state.active_user.lock().unwrap().clone();
+    let profiles = load_profiles();
+    [requested.map(str::to_string), active]
+        .into_iter()
+        .flatten()
+        .find_map(|name| profiles.iter().find(|profile| profile.answers_to(&name)).cloned())
+        .unwrap_or_else(|| profiles[0].clone())
+}
+
+/// Reads the `X-Mirror-User` header, how apps and per-person devices say who is talking.
+pub fn header_user(headers: &http::HeaderMap) -> Option<String> {
+    headers.get("x-mirror-user")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+pub fn set_active(app_state: &AppState, name: &str) -> Option<UserProfile> {
+    let profile = find(name)?;
+    *app_state.active_user.lock().unwrap() = Some(profile.id.clone());
+    push::publish(app_state, MirrorEvent::ActiveUser {
+        id: profile.id.clone(),
+        name: profile.name.clone(),
+        units: profile.units,
+    });
+    Some(profile)
+}
+
+pub fn remember(profile_id: &str, fact: &str) -> std::io::Result<()> {
+    let mut profiles = load_profiles();
+    if let Some(profile) = profiles.iter_mut().find(|profile| profile.id == profile_id) {
+        profile.memory.push(fact.to_string());
+    }
+    save_profiles(&profiles)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ActiveUserRequest {
+    pub user: String,
+}
+
+pub async fn list_profiles() -> Json<Vec<UserProfile>> {
+    Json(load_profiles())
+}
+
+pub async fn get_active_profile(State(app_state): State<AppState>) -> Json<UserProfile> {
+    Json(resolve(&app_state, None))
+}
+
+/// Lets a presence sensor (or anything else) say who is standing at the mirror.
+pub async fn set_active_profile(
+    State(app_state): State<AppState>,
+    Json(payload): Json<ActiveUserRequest>
+) -> Result<Json<UserProfile>, http::StatusCode> {
+    set_active(&app_state, &payload.user)
+        .map(Json)
+        .ok_or(http::StatusCode::NOT_FOUND)
+}
@@ -0,0 +1,95 @@
+use axum::{extract::State, response::sse::{Event, KeepAlive, Sse}};
+use dotenv::dotenv;
+use serde::Serialize;
+use std::{collections::BTreeMap, convert::Infallible, env};
+use tokio_stream::{Stream, StreamExt, wrappers::BroadcastStream};
+
+use crate::calendar_api::{self, CalendarEvent};
+use crate::lists::ListItem;
+use crate::news::Headline;
+use crate::profiles::{self, Units};
+use crate::state::AppState;
+use crate::timers::Timer;
+use crate::weather_api::{self, WeatherResponse};
+
+
+/// Messages pushed to every connected display over `/events`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MirrorEvent {
+    Weather { weather: WeatherResponse },
+    Calendar { events: Vec<CalendarEvent> },
+    Alert { text: String },
+    Speak { clip_id: String, text: String },
+    Speaking { text: String },
+    /// One finished chat exchange.
+    Interaction { user: String, message: String, command: String, text: String },
+    ActiveUser { id: String, name: String, units: Units },
+    Timers { timers: Vec<Timer> },
+    Lists { lists: BTreeMap<String, Vec<ListItem>> },
+    News { headlines: Vec<Headline> },
+}
+
+pub fn channel() -> tokio::sync::broadcast::Sender<MirrorEvent> {
+    tokio::sync::broadcast::channel(64).0
+}
+
+pub fn publish(app_state: &AppState, event: MirrorEvent) {
+    // Sending only fails when no display is connected, which is fine.
+    let _ = app_state.events.send(event);
+}
+
+pub async fn event_stream(State(app_state): State<AppState>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = BroadcastStream::new(app_state.events.subscribe())
+        .filter_map(|message| match message {
+            Ok(event) => Event::default().json_data(&event).ok().map(Ok),
+            Err(err) => {
+                tracing::warn!("Display fell behind on events: {}", err);
+                None
+            }
+        });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+pub async fn publish_calendar(app_state: AppState) {
+    let profile = profiles::resolve(&app_state, None);
+    let events = calendar_api::get_calendar_events(&profile.calendars).await;
+    publish(&app_state, MirrorEvent::Calendar { events });
+}
+
+pub async fn publish_weather(app_state: &AppState) {
+    let location = app_state.display_location.lock().unwrap().clone();
+    let Some(location) = location else {
+        return;
+    };
+    match weather_api::get_weather(&location).await {
+        Ok(weather) => publish(app_state, MirrorEvent::Weather { weather }),
+        Err(err) => tracing::warn!("Failed to refresh weather: {}", err),
+    }
+}
+
+/// Periodically re-reads weather and calendar so displays stay current without polling.
+/// `PUSH_REFRESH_MINUTES=0` turns this off.
+pub async fn run_refresh(app_state: AppState) {
+    dotenv().ok();
+    let minutes: u64 = env::var("PUSH_REFRESH_MINUTES").ok()
+        .and_then(|m| m.parse().ok())
+        .unwrap_or(15);
+    if minutes == 0 {
+        return;
+    }
+
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(minutes * 60));
+    // The first tick fires immediately; displays already load everything on startup.
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if app_state.events.receiver_count() == 0 {
+            continue;
+        }
+        publish_weather(&app_state).await;
+        if env::var("GOOGLE_CREDENTIALS_PATH").is_ok() {
+            publish_calendar(app_state.clone()).await;
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use axum::{Json, body::Bytes, extract::{Query, State}, response::Response};
+use dotenv::dotenv;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::{env, io::Write};
+use tempfile::NamedTempFile;
+use tokio::process::Command;
+
+use crate::chat;
+use crate::state::AppState;
+use crate::text_to_speech;
+use crate::usage;
+
+pub type SttError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TranscribeResponse {
+    pub text: String,
+}
+
+/// Where uploaded audio gets turned into text, picked with `STT_BACKEND`.
+pub enum SttBackend {
+    WhisperCpp { binary: String, model: String },
+    OpenAi { api_key: String, model: String },
+    Mock { text: String },
+}
+
+impl SttBackend {
+    pub fn from_env() -> Option<SttBackend> {
+        dotenv().ok();
+        match env::var("STT_BACKEND").unwrap_or_default().as_str() {
+            "whisper_cpp" => Some(SttBackend::WhisperCpp {
+                binary: env::var("WHISPER_CPP_BIN").unwrap_or_else(|_| "whisper-cli".to_string()),
+                model: env::var("WHISPER_CPP_MODEL").ok()?,
+            }),
+            "openai" => Some(SttBackend::OpenAi {
+                api_key: env::var("OPENAI_API_KEY").ok()?,
+                model: env::var("OPENAI_STT_MODEL").unwrap_or_else(|_| "whisper-1".to_string()),
+            }),
+            "mock" => Some(SttBackend::Mock {
+                text: env::var("STT_MOCK_TEXT").unwrap_or_default(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Whether audio stays on this machine.
+    pub fn is_local(&self) -> bool {
+        !matches!(self, SttBackend::OpenAi { .. })
+    }
+
+    pub async fn transcribe(&self, app_state: &AppState, audio: Bytes, extension: &str) -> Result<String, SttError> {
+        match self {
+            SttBackend::WhisperCpp { binary, model } => transcribe_whisper_cpp(binary, model, audio, extension).await,
+            SttBackend::OpenAi { api_key, model } => {
+                if !usage::stt_allowed(app_state) {
+                    return Err(usage::OverBudget("transcription").into());
+                }
+                transcribe_openai(app_state, api_key, model, audio, extension).await.map_err(crate::logging::without_url)
+            },
+            SttBackend::Mock { text } => Ok(text.clone()),
+        }
+    }
+}
+
+/// Maps an upload's content type to the file extension the backends expect.
+pub fn audio_extension(content_type: &str) -> Option<&'static str> {
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    match mime {
+        "audio/wav" | "audio/x-wav" | "audio/wave" => Some("wav"),
+        "audio/webm" | "video/webm" => Some("webm"),
+        "audio/ogg" | "audio/opus" => Some("ogg"),
+        _ => None,
+    }
+}
+
+/// A private, randomly named file for the external tools to read, deleted when dropped.
+fn upload_file(extension: &str) -> std::io::Result<NamedTempFile> {
+    tempfile::Builder::new()
+        .prefix("mirror-stt-")
+        .suffix(&format!(".{}", extension))
+        .tempfile()
+}
+
+async fn transcribe_whisper_cpp(binary: &str, model: &str, audio: Bytes, extension: &str) -> Result<String, SttError> {
+    let mut input = upload_file(extension)?;
+    input.write_all(&audio)?;
+    input.flush()?;
+
+    // whisper.cpp only reads 16 kHz mono WAV, so anything else goes through ffmpeg first.
+    let converted = if extension == "wav" {
+        None
+    } else {
+        let wav = upload_file("wav")?;
+        let status = Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error", "-i"])
+            .arg(input.path())
+            .args(["-ar", "16000", "-ac", "1"])
+            .arg(wav.path())
+            .status()
+            .await;
+        if !matches!(status, Ok(s) if s.success()) {
+            return Err("ffmpeg could not convert the upload to WAV".into());
+        }
+        Some(wav)
+    };
+    let wav = converted.as_ref().unwrap_or(&input);
+
+    let output = Command::new(binary)
+        .args(["-m", model, "-nt", "-np", "-f"])
+        .arg(wav.path())
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(format!("whisper.cpp failed: {}", String::from_utf8_lossy(&output.stderr)).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// OpenAI's verbose transcript, which says how much audio it billed for.
+#[derive(Deserialize)]
+struct OpenAiTranscript {
+    text: String,
+    duration: Option<f64>,
+}
+
+async fn transcribe_openai(
+    app_state: &AppState,
+    api_key: &str,
+    model: &str,
+    audio: Bytes,
+    extension: &str
+) -> Result<String, SttError> {
+    // Without a reported duration, assume 16 kHz 16-bit audio, which is what `/listen` sends.
+    let estimate = audio.len() as f64 / 32_000.0;
+    let file = reqwest::multipart::Part::bytes(audio.to_vec())
+        .file_name(format!("audio.{}", extension));
+    let form = reqwest::multipart::Form::new()
+        .text("model", model.to_string())
+        .text("response_format", "verbose_json")
+        .part("file", file);
+
+    let response = Client::new()
+        .post("https://api.openai.com/v1/audio/transcriptions")
+        .header("Authorization", format!("Bearer {}", api_key))
+        .multipart(form)
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(format!("OpenAI transcription failed with {}", response.status()).into());
+    }
+    let transcript: OpenAiTranscript = response.json().await?;
+    usage::record_stt(app_state, transcript.duration.unwrap_or(estimate));
+    Ok(transcript.text.trim().to_string())
+}
+
+pub async fn transcribe(app_state: &AppState, headers: &http::HeaderMap, audio: Bytes) -> Result<String, http::StatusCode> {
+    if audio.is_empty() {
+        return Err(http::StatusCode::BAD_REQUEST);
+    }
+    let content_type = headers.get(http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let extension = audio_extension(content_type).ok_or(http::StatusCode::UNSUPPORTED_MEDIA_TYPE)?;
+
+    let Some(backend) = SttBackend::from_env() else {
+        tracing::warn!("No STT_BACKEND configured");
+        return Err(http::StatusCode::SERVICE_UNAVAILABLE);
+    };
+
+    backend.transcribe(app_state, audio, extension).await.map_err(|err| {
+        tracing::warn!("Failed to transcribe audio: {}", err);
+        if err.is::<usage::OverBudget>() {
+            http::StatusCode::TOO_MANY_REQUESTS
+        } else {
+            http::StatusCode::BAD_GATEWAY
+        }
+    })
+}
+
+pub async fn transcribe_json(
+    State(app_state): State<AppState>,
+    headers: http::HeaderMap,
+    audio: Bytes
+) -> Result<Json<TranscribeResponse>, http::StatusCode> {
+    let text = transcribe(&app_state, &headers, audio).await?;
+    Ok(Json(TranscribeResponse { text }))
+}
+
+/// Same as `/chat`, but the message is spoken instead of typed.
+pub async fn chat_audio(
+    State(app_state): State<AppState>,
+    Query(options): Query<text_to_speech::ResponseOptions>,
+    headers: http::HeaderMap,
+    audio: Bytes
+) -> Result<Response, http::StatusCode> {
+    let message = transcribe(&app_state, &headers, audio).await?;
+    tracing::debug!(text = %crate::logging::content(&message), "Transcribed");
+    if message.is_empty() {
+        return Err(http::StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    text_to_speech::chat_audio_response(app_state, chat::ChatRequest{message, user: None, voice: None}, &options, &headers).await
+}
@@ -1,5 +1,19 @@
-
 #[derive(Clone)]
 pub struct AppState {
-    pub messages: std::sync::Arc<std::sync::Mutex<Vec<serde_json::Value>>>,
-}
\ No newline at end of file
+    /// Conversation history per profile id.
+    pub messages: std::sync::Arc<std::sync::Mutex<std::collections::HashMap<String, Vec<serde_json::Value>>>>,
+    pub reminders: std::sync::Arc<std::sync::Mutex<crate::event_reminders::ReminderState>>,
+    pub events: tokio::sync::broadcast::Sender<crate::push::MirrorEvent>,
+    pub clips: std::sync::Arc<std::sync::Mutex<crate::text_to_speech::ClipStore>>,
+    pub display_location: std::sync::Arc<std::sync::Mutex<Option<String>>>,
+    /// Voices picked by saying so, per profile id, until the server restarts.
+    pub session_voice: std::sync::Arc<std::sync::Mutex<std::collections::HashMap<String, String>>>,
+    pub timers: std::sync::Arc<std::sync::Mutex<crate::timers::TimerStore>>,
+    pub lists: std::sync::Arc<std::sync::Mutex<crate::lists::ListStore>>,
+    pub news: std::sync::Arc<std::sync::Mutex<Vec<crate::news::Headline>>>,
+    pub announcements: std::sync::Arc<std::sync::Mutex<crate::announce::AnnounceQueue>>,
+    pub api_keys: std::sync::Arc<std::sync::Mutex<crate::auth::KeyStore>>,
+    pub usage: std::sync::Arc<std::sync::Mutex<crate::usage::UsageTracker>>,
+    pub guard: std::sync::Arc<std::sync::Mutex<crate::guard::GuardState>>,
+    pub active_user: std::sync::Arc<std::sync::Mutex<Option<String>>>,
+}
@@ -1,57 +1,420 @@
-
-use axum::{Json, response::{Response}, extract::State};
-use std::env;
+use axum::{Json, response::{Response, IntoResponse}, extract::{State, Path, Query}, body::Bytes};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, env};
 use reqwest::Client;
+use tokio::process::Command;
+use tracing::Instrument;
 
 use crate::state::AppState;
 
 use crate::chat;
+use crate::push::{self, MirrorEvent};
+use crate::tts_cache;
+use crate::usage;
+use crate::voices::{self, Prosody, VoiceProfile};
+
+pub type TtsError = Box<dyn std::error::Error + Send + Sync>;
+
+const MAX_CLIPS: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct Speech {
+    pub audio: Bytes,
+    pub content_type: &'static str,
+}
 
+/// Recently spoken clips, fetched by displays after a `Speak` event.
+#[derive(Default)]
+pub struct ClipStore {
+    next_id: u64,
+    clips: VecDeque<(String, Speech)>,
+}
+
+impl ClipStore {
+    pub fn insert(&mut self, speech: Speech) -> String {
+        self.next_id += 1;
+        let id = self.next_id.to_string();
+        self.clips.push_back((id.clone(), speech));
+        if self.clips.len() > MAX_CLIPS {
+            self.clips.pop_front();
+        }
+        id
+    }
+
+    pub fn get(&self, id: &str) -> Option<Speech> {
+        self.clips.iter().find(|(clip_id, _)| clip_id == id).map(|(_, speech)| speech.clone())
+    }
+}
+
+
+/// How `/chat` answers: bare audio by default, or JSON with `?format=json`.
+#[derive(Deserialize, Debug, Default)]
+pub struct ResponseOptions {
+    pub format: Option<String>,
+    /// With `format=json`: `inline` puts base64 audio in the body, `url` links to `/clips/:id`.
+    pub audio: Option<String>,
+}
+
+impl ResponseOptions {
+    fn wants_json(&self, headers: &http::HeaderMap) -> bool {
+        let accepts_json = headers.get(http::header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|accept| accept.starts_with("application/json"));
+        self.format.as_deref() == Some("json") || accepts_json
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChatAudioResponse {
+    #[serde(flatten)]
+    pub chat: chat::ChatResponse,
+    pub content_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio_base64: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio_url: Option<String>,
+}
+
+/// Sent with a 503 when no backend could speak the reply, so the display can show the text.
+#[derive(Serialize, Debug)]
+pub struct SpeechUnavailable {
+    pub error: &'static str,
+    pub detail: String,
+    #[serde(flatten)]
+    pub chat: chat::ChatResponse,
+}
 
 #[axum::debug_handler]
 pub async fn return_audio(
-    State(app_state): State<AppState>, 
+    State(app_state): State<AppState>,
+    Query(options): Query<ResponseOptions>,
+    headers: http::HeaderMap,
     Json(payload): Json<chat::ChatRequest>
 ) -> Result<Response, http::StatusCode> {
+    chat_audio_response(app_state, payload, &options, &headers).await
+}
+
+pub async fn chat_audio_response(
+    app_state: AppState,
+    mut request: chat::ChatRequest,
+    options: &ResponseOptions,
+    headers: &http::HeaderMap
+) -> Result<Response, http::StatusCode> {
+    if request.user.is_none() {
+        request.user = crate::profiles::header_user(headers);
+    }
 
-    let chat_str = chat::handle_chat_request(
-        app_state.messages.clone(),
-        Json(chat::ChatRequest{message: payload.message})
+    let voice = voices::select(&app_state, request.voice.as_deref(), request.user.as_deref());
+    // Held until the reply is handed over, or has failed, rather than just while the model thinks.
+    let _hold = crate::announce::conversation_started(&app_state);
+    let Json(chat_str) = chat::handle_chat_request(
+        app_state.clone(),
+        Json(request)
     ).await;
 
 
-    println!("Chat_Str:{}", &chat_str.text);
+    tracing::debug!(reply = %crate::logging::content(&chat_str.text), "Synthesizing reply");
 
+    let speech = match synthesize(&app_state, &chat_str.text, &voice, chat_str.prosody.as_ref()).await {
+        Ok(speech) => speech,
+        Err(err) => {
+            tracing::warn!("Failed to synthesize speech: {}", err);
+            let body = SpeechUnavailable {
+                error: "tts_unavailable",
+                detail: err.to_string(),
+                chat: chat_str,
+            };
+            return Ok((http::StatusCode::SERVICE_UNAVAILABLE, Json(body)).into_response());
+        }
+    };
 
-    let v_id = env::var("VOICE_ID").expect("NO VOICE ID");
-    let api_key = env::var("ELEVENLABS_API_KEY").expect("NO 11L KEY");
+    crate::announce::hold_for(&app_state, &chat_str.text);
+    push::publish(&app_state, MirrorEvent::Speaking { text: chat_str.text.clone() });
 
-    let client = Client::new();
-    let url = format!("https://api.elevenlabs.io/v1/text-to-speech/{}", v_id);
-    let body = serde_json::json!({
-        "text": chat_str.text,
-        "voice_settings": {
-            "stability": 0.5,
-            "similarity_boost": 0.7
+    if options.wants_json(headers) {
+        let content_type = speech.content_type.to_string();
+        let (audio_base64, audio_url) = if options.audio.as_deref() == Some("url") {
+            let clip_id = app_state.clips.lock().unwrap().insert(speech);
+            (None, Some(format!("/clips/{}", clip_id)))
+        } else {
+            (Some(base64::engine::general_purpose::STANDARD.encode(&speech.audio)), None)
+        };
+        return Ok(Json(ChatAudioResponse {
+            chat: chat_str,
+            content_type,
+            audio_base64,
+            audio_url,
+        }).into_response());
+    }
+
+    let response = Response::builder()
+        .status(http::StatusCode::OK)
+        .header(http::header::CONTENT_TYPE, speech.content_type)
+        .body(axum::body::boxed(axum::body::Body::from(speech.audio)))
+        .unwrap();
+
+    Ok(response)
+
+}
+
+/// Synthesizes a conversation reply and tells every display to play it right away.
+/// Anything unprompted should go through `announce::enqueue` instead.
+pub async fn speak(app_state: &AppState, text: &str) -> Result<String, TtsError> {
+    let voice = voices::select(app_state, None, None);
+    let speech = synthesize(app_state, text, &voice, None).await?;
+    crate::announce::hold_for(app_state, text);
+    let clip_id = app_state.clips.lock().unwrap().insert(speech);
+    push::publish(app_state, MirrorEvent::Speak { clip_id: clip_id.clone(), text: text.to_string() });
+    Ok(clip_id)
+}
+
+pub async fn get_clip(
+    State(app_state): State<AppState>,
+    Path(clip_id): Path<String>
+) -> Result<Response, http::StatusCode> {
+    let speech = app_state.clips.lock().unwrap().get(&clip_id).ok_or(http::StatusCode::NOT_FOUND)?;
+    let response = Response::builder()
+        .status(http::StatusCode::OK)
+        .header(http::header::CONTENT_TYPE, speech.content_type)
+        .body(axum::body::boxed(axum::body::Body::from(speech.audio)))
+        .unwrap();
+    Ok(response)
+}
+
+/// Speech engines, tried in the order given by `TTS_BACKENDS`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TtsBackend {
+    ElevenLabs,
+    OpenAi,
+    Espeak,
+}
+
+impl TtsBackend {
+    fn parse(name: &str) -> Option<TtsBackend> {
+        match name.trim() {
+            "elevenlabs" => Some(TtsBackend::ElevenLabs),
+            "openai" => Some(TtsBackend::OpenAi),
+            "espeak" => Some(TtsBackend::Espeak),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TtsBackend::ElevenLabs => "elevenlabs",
+            TtsBackend::OpenAi => "openai",
+            TtsBackend::Espeak => "espeak",
+        }
+    }
+
+    pub async fn synthesize(
+        &self,
+        app_state: &AppState,
+        text: &str,
+        voice: &VoiceProfile,
+        prosody: Option<&Prosody>
+    ) -> Result<Speech, TtsError> {
+        match self {
+            TtsBackend::ElevenLabs => synthesize_elevenlabs(app_state, text, voice, prosody).await,
+            TtsBackend::OpenAi => synthesize_openai(app_state, text, voice, prosody).await,
+            TtsBackend::Espeak => synthesize_espeak(text, voice, prosody).await,
         }
+    }
+}
+
+pub fn backends() -> Vec<TtsBackend> {
+    env::var("TTS_BACKENDS")
+        .unwrap_or_else(|_| "elevenlabs".to_string())
+        .split(',')
+        .filter_map(|name| {
+            let backend = TtsBackend::parse(name);
+            if backend.is_none() {
+                tracing::warn!("Unknown TTS backend: {}", name);
+            }
+            backend
+        })
+        .collect()
+}
+
+/// Tries each configured backend in turn and returns the first clip that works.
+/// Once the speech budget (`TTS_DAILY_CHARACTERS`, `TTS_MONTHLY_CHARACTERS`) runs out espeak gets a turn too, even if it isn't configured.
+pub async fn synthesize(
+    app_state: &AppState,
+    text: &str,
+    voice: &VoiceProfile,
+    prosody: Option<&Prosody>
+) -> Result<Speech, TtsError> {
+    let mut failures = Vec::new();
+    let mut backends = backends();
+    let mut tried = 0;
+    while let Some(&backend) = backends.get(tried) {
+        tried += 1;
+        let span = tracing::info_span!("tts", backend = backend.name(), chars = text.chars().count());
+        match backend.synthesize(app_state, text, voice, prosody).instrument(span).await {
+            Ok(speech) => return Ok(speech),
+            Err(err) => {
+                let err = crate::logging::without_url(err);
+                tracing::warn!("TTS backend {} failed: {}", backend.name(), err);
+                failures.push(format!("{}: {}", backend.name(), err));
+                if err.is::<usage::OverBudget>() && !backends.contains(&TtsBackend::Espeak) {
+                    backends.push(TtsBackend::Espeak);
+                }
+            }
+        }
+    }
+    if failures.is_empty() {
+        return Err("No TTS backends configured".into());
+    }
+    Err(format!("All TTS backends failed ({})", failures.join("; ")).into())
+}
+
+/// Turns a non-2xx upstream answer into an error that carries the provider's message.
+async fn check_audio_response(provider: &str, response: reqwest::Response) -> Result<Bytes, TtsError> {
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("{} returned {}: {}", provider, status, body.chars().take(200).collect::<String>()).into());
+    }
+    let is_audio = response.headers().get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("audio/"));
+    if !is_audio {
+        return Err(format!("{} did not return audio", provider).into());
+    }
+    Ok(response.bytes().await?)
+}
+
+async fn synthesize_elevenlabs(
+    app_state: &AppState,
+    text: &str,
+    voice: &VoiceProfile,
+    prosody: Option<&Prosody>
+) -> Result<Speech, TtsError> {
+    let api_key = env::var("ELEVENLABS_API_KEY")?;
+    if voice.voice_id.is_empty() {
+        return Err("voice profile has no ElevenLabs voice_id".into());
+    }
+
+    // ElevenLabs understands <break> tags but has no emphasis control, so emphasis is dropped.
+    let text = match prosody {
+        Some(prosody) => prosody.insert_breaks(text, |seconds| format!("<break time=\"{:.1}s\" />", seconds)),
+        None => text.to_string(),
+    };
+    let speed = voice.speaking_rate * prosody.and_then(|p| p.rate).unwrap_or(1.0);
+    let voice_settings = serde_json::json!({
+        "stability": voice.stability,
+        "similarity_boost": voice.similarity_boost,
+        "speed": speed.clamp(0.7, 1.2)
     });
+    let cache_key = tts_cache::cache_key(
+        &text,
+        &format!("{}/{}", voice.voice_id, voice.model_id.as_deref().unwrap_or_default()),
+        &voice_settings
+    );
+    if let Some(audio) = tts_cache::get(&cache_key).await {
+        return Ok(Speech { audio, content_type: "audio/mpeg" });
+    }
+    if !usage::tts_allowed(app_state, &text) {
+        return Err(usage::OverBudget("speech").into());
+    }
+
+    let client = Client::new();
+    let url = format!("https://api.elevenlabs.io/v1/text-to-speech/{}", voice.voice_id);
+    let mut body = serde_json::json!({
+        "text": text,
+        "voice_settings": voice_settings
+    });
+    if let Some(model_id) = &voice.model_id {
+        body["model_id"] = serde_json::json!(model_id);
+    }
 
     let response = client.post(&url)
         .header("xi-api-key", api_key)
         .header("Content-Type", "application/json")
         .json(&body)
         .send()
-        .await
-        .unwrap();
+        .await?;
 
-    let audio_bytes = response.bytes().await.unwrap();
+    let audio = check_audio_response("ElevenLabs", response).await?;
+    usage::record_tts(app_state, &text);
+    tts_cache::put(&cache_key, &audio).await;
+    Ok(Speech { audio, content_type: "audio/mpeg" })
+}
 
-    let response = Response::builder()
-        .status(http::StatusCode::OK)
-        .header(http::header::CONTENT_TYPE, "audio/mpeg")
-        .body(axum::body::boxed(axum::body::Body::from(audio_bytes)))
-        .unwrap();
+async fn synthesize_openai(
+    app_state: &AppState,
+    text: &str,
+    voice: &VoiceProfile,
+    prosody: Option<&Prosody>
+) -> Result<Speech, TtsError> {
+    let api_key = env::var("OPENAI_API_KEY")?;
+    let model = env::var("OPENAI_TTS_MODEL").unwrap_or_else(|_| "tts-1".to_string());
+    let openai_voice = voice.openai_voice.clone()
+        .unwrap_or_else(|| env::var("OPENAI_TTS_VOICE").unwrap_or_else(|_| "alloy".to_string()));
 
-    Ok(response)
+    // No markup support, an ellipsis is the closest thing to a pause.
+    let text = match prosody {
+        Some(prosody) => prosody.insert_breaks(text, |_| "...".to_string()),
+        None => text.to_string(),
+    };
+    let speed = (voice.speaking_rate * prosody.and_then(|p| p.rate).unwrap_or(1.0)).clamp(0.25, 4.0);
+    let cache_key = tts_cache::cache_key(
+        &text,
+        &format!("openai/{}", openai_voice),
+        &serde_json::json!({ "model": model, "speed": speed })
+    );
+    if let Some(audio) = tts_cache::get(&cache_key).await {
+        return Ok(Speech { audio, content_type: "audio/mpeg" });
+    }
+    if !usage::tts_allowed(app_state, &text) {
+        return Err(usage::OverBudget("speech").into());
+    }
+
+    let response = Client::new()
+        .post("https://api.openai.com/v1/audio/speech")
+        .header("Authorization", format!("Bearer {}", api_key))
+        .json(&serde_json::json!({
+            "model": model,
+            "voice": openai_voice,
+            "input": text,
+            "speed": speed,
+            "response_format": "mp3"
+        }))
+        .send()
+        .await?;
+
+    let audio = check_audio_response("OpenAI", response).await?;
+    usage::record_tts(app_state, &text);
+    tts_cache::put(&cache_key, &audio).await;
+    Ok(Speech { audio, content_type: "audio/mpeg" })
+}
+
+fn escape_ssml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Local espeak-ng: robotic, but free and works without a network.
+async fn synthesize_espeak(text: &str, voice: &VoiceProfile, prosody: Option<&Prosody>) -> Result<Speech, TtsError> {
+    let binary = env::var("ESPEAK_BIN").unwrap_or_else(|_| "espeak-ng".to_string());
+
+    // espeak reads SSML, so both pauses and emphasis survive here.
+    let mut ssml = escape_ssml(text);
+    if let Some(prosody) = prosody {
+        ssml = prosody.wrap_emphasis(&ssml, |word| format!("<emphasis>{}</emphasis>", word));
+        ssml = prosody.insert_breaks(&ssml, |seconds| format!("<break time=\"{}ms\"/>", (seconds * 1000.0) as u64));
+    }
+    let rate = voice.speaking_rate * prosody.and_then(|p| p.rate).unwrap_or(1.0);
+    let words_per_minute = (175.0 * rate).clamp(80.0, 450.0) as u32;
 
+    let mut command = Command::new(binary);
+    command.args(["-m", "--stdout", "-s", &words_per_minute.to_string()]);
+    if let Some(espeak_voice) = &voice.espeak_voice {
+        command.args(["-v", espeak_voice]);
+    }
+    let output = command.arg(format!("<speak>{}</speak>", ssml)).output().await?;
+    if !output.status.success() || output.stdout.is_empty() {
+        return Err(format!("espeak failed: {}", String::from_utf8_lossy(&output.stderr)).into());
+    }
+    Ok(Speech { audio: Bytes::from(output.stdout), content_type: "audio/wav" })
 }
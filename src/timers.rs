@@ -0,0 +1,282 @@
+use axum::{Json, extract::{Path, State}};
+use chrono::{DateTime, Duration, NaiveDateTime, NaiveTime, Utc};
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use std::{env, fs};
+
+use crate::announce::{self, Priority};
+use crate::places;
+use crate::push::{self, MirrorEvent};
+use crate::state::AppState;
+
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimerKind {
+    Timer,
+    Alarm,
+    Stopwatch,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Timer {
+    pub id: u64,
+    pub kind: TimerKind,
+    #[serde(default)]
+    pub label: Option<String>,
+    pub started_at: DateTime<Utc>,
+    /// When it rings, stopwatches only count up.
+    #[serde(default)]
+    pub ends_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub ringing: bool,
+}
+
+impl Timer {
+    pub fn name(&self) -> String {
+        let kind = match self.kind {
+            TimerKind::Timer => "timer",
+            TimerKind::Alarm => "alarm",
+            TimerKind::Stopwatch => "stopwatch",
+        };
+        match &self.label {
+            Some(label) => format!("{} {}", label, kind),
+            None => kind.to_string(),
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        let name = name.trim().to_lowercase();
+        self.id.to_string() == name || self.name().to_lowercase().contains(&name)
+    }
+
+    pub fn ring_text(&self) -> String {
+        match self.kind {
+            TimerKind::Alarm => format!("Your {} is going off.", self.name()),
+            _ => format!("Your {} is done.", self.name()),
+        }
+    }
+
+    /// How the timer stands right now, for the assistant to read out.
+    pub fn status_text(&self, now: DateTime<Utc>) -> String {
+        match (self.kind, self.ends_at) {
+            _ if self.ringing => format!("{}: ringing", self.name()),
+            (TimerKind::Alarm, Some(ends_at)) => {
+                let local = ends_at.with_timezone(&places::home_now().timezone());
+                format!("{}: set for {}", self.name(), local.format("%A %H:%M"))
+            },
+            (_, Some(ends_at)) => format!("{}: {} left", self.name(), spoken_duration(ends_at - now)),
+            (_, None) => format!("{}: running for {}", self.name(), spoken_duration(now - self.started_at)),
+        }
+    }
+}
+
+pub fn spoken_duration(duration: Duration) -> String {
+    let seconds = duration.num_seconds().max(0);
+    let (hours, minutes, seconds) = (seconds / 3600, seconds % 3600 / 60, seconds % 60);
+    let mut parts = Vec::new();
+    for (amount, unit) in [(hours, "hour"), (minutes, "minute"), (seconds, "second")] {
+        match amount {
+            0 => {},
+            1 => parts.push(format!("1 {}", unit)),
+            n => parts.push(format!("{} {}s", n, unit)),
+        }
+    }
+    if parts.is_empty() {
+        return "0 seconds".to_string();
+    }
+    parts.join(" ")
+}
+
+/// Every timer, alarm and stopwatch, saved to `TIMERS_PATH` after each change so they survive restarts.
+#[derive(Deserialize, Serialize, Default)]
+pub struct TimerStore {
+    next_id: u64,
+    timers: Vec<Timer>,
+}
+
+fn timers_path() -> String {
+    dotenv().ok();
+    env::var("TIMERS_PATH").unwrap_or_else(|_| "timers.json".to_string())
+}
+
+/// How long a timer keeps ringing before it is cleared on its own.
+fn ring_limit() -> Duration {
+    dotenv().ok();
+    Duration::minutes(env::var("TIMER_RING_MINUTES").ok().and_then(|m| m.parse().ok()).unwrap_or(10))
+}
+
+impl TimerStore {
+    pub fn load() -> TimerStore {
+        let path = timers_path();
+        match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+                tracing::warn!("Error parsing timers in {}: {}", path, err);
+                TimerStore::default()
+            }),
+            Err(_) => TimerStore::default(),
+        }
+    }
+
+    fn save(&self) {
+        let result = serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::from)
+            .and_then(|contents| fs::write(timers_path(), contents));
+        if let Err(err) = result {
+            tracing::error!("Failed to save timers: {}", err);
+        }
+    }
+
+    pub fn list(&self) -> Vec<Timer> {
+        self.timers.clone()
+    }
+
+    pub fn add(&mut self, kind: TimerKind, label: Option<String>, ends_at: Option<DateTime<Utc>>) -> Timer {
+        self.next_id += 1;
+        let timer = Timer {
+            id: self.next_id,
+            kind,
+            label: label.filter(|label| !label.trim().is_empty()),
+            started_at: Utc::now(),
+            ends_at,
+            ringing: false,
+        };
+        self.timers.push(timer.clone());
+        self.save();
+        timer
+    }
+
+    /// Removes the named timer, or everything for "all". Without a name it stops whatever
+    /// is ringing, else the newest one.
+    pub fn cancel(&mut self, name: Option<&str>) -> Vec<Timer> {
+        let name = name.map(str::trim).filter(|name| !name.is_empty());
+        let ringing = self.timers.iter().any(|timer| timer.ringing);
+        let newest = self.timers.iter().map(|timer| timer.id).max();
+        let (cancelled, kept): (Vec<Timer>, Vec<Timer>) = self.timers.drain(..).partition(|timer| match name {
+            Some(name) if name.eq_ignore_ascii_case("all") => true,
+            Some(name) => timer.matches(name),
+            None if ringing => timer.ringing,
+            None => Some(timer.id) == newest,
+        });
+        self.timers = kept;
+        if !cancelled.is_empty() {
+            self.save();
+        }
+        cancelled
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<Timer> {
+        let position = self.timers.iter().position(|timer| timer.id == id)?;
+        let timer = self.timers.remove(position);
+        self.save();
+        Some(timer)
+    }
+
+    /// Like `remove`, but only for a timer that is ringing right now.
+    pub fn stop_ringing(&mut self, id: u64) -> Option<Timer> {
+        if !self.timers.iter().any(|timer| timer.id == id && timer.ringing) {
+            return None;
+        }
+        self.remove(id)
+    }
+
+    /// Pushes everything that is ringing `minutes` into the future.
+    pub fn snooze(&mut self, now: DateTime<Utc>, minutes: i64) -> Vec<Timer> {
+        let mut snoozed = Vec::new();
+        for timer in self.timers.iter_mut().filter(|timer| timer.ringing) {
+            timer.ringing = false;
+            timer.ends_at = Some(now + Duration::minutes(minutes));
+            snoozed.push(timer.clone());
+        }
+        if !snoozed.is_empty() {
+            self.save();
+        }
+        snoozed
+    }
+
+    /// Starts ringing whatever ran out and clears what has rung for too long.
+    /// Returns the newly ringing timers and whether anything changed.
+    fn tick(&mut self, now: DateTime<Utc>, ring_limit: Duration) -> (Vec<Timer>, bool) {
+        let before = self.timers.len();
+        self.timers.retain(|timer| !(timer.ringing && timer.ends_at.is_some_and(|at| at + ring_limit <= now)));
+        let mut changed = self.timers.len() != before;
+
+        let mut expired = Vec::new();
+        for timer in self.timers.iter_mut() {
+            if !timer.ringing && timer.ends_at.is_some_and(|at| at <= now) {
+                timer.ringing = true;
+                expired.push(timer.clone());
+            }
+        }
+        changed |= !expired.is_empty();
+        if changed {
+            self.save();
+        }
+        (expired, changed)
+    }
+}
+
+/// Reads "07:00" as the next time the home clock shows it, or a full "yyyy-mm-ddTHH:MM".
+pub fn parse_alarm_time(time: &str) -> Option<DateTime<Utc>> {
+    let time = time.trim();
+    let full = ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(time, format).ok());
+    if let Some(naive) = full {
+        return places::from_home_time(naive);
+    }
+
+    let clock = ["%H:%M", "%H:%M:%S", "%I:%M %p"].iter()
+        .find_map(|format| NaiveTime::parse_from_str(&time.to_uppercase(), format).ok())?;
+    let today = places::home_now().date_naive();
+    let at = places::from_home_time(today.and_time(clock))?;
+    if at > Utc::now() {
+        Some(at)
+    } else {
+        places::from_home_time((today + Duration::days(1)).and_time(clock))
+    }
+}
+
+pub fn publish_timers(app_state: &AppState) {
+    let timers = app_state.timers.lock().unwrap().list();
+    push::publish(app_state, MirrorEvent::Timers { timers });
+}
+
+pub async fn run_scheduler(app_state: AppState) {
+    let ring_limit = ring_limit();
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let (expired, changed) = app_state.timers.lock().unwrap().tick(Utc::now(), ring_limit);
+        if changed {
+            publish_timers(&app_state);
+        }
+        for timer in expired {
+            let text = timer.ring_text();
+            announce::enqueue(&app_state, &text, Priority::High);
+        }
+    }
+}
+
+pub async fn list_timers(State(app_state): State<AppState>) -> Json<Vec<Timer>> {
+    Json(app_state.timers.lock().unwrap().list())
+}
+
+/// Cancels any timer, for apps with a chat key.
+pub async fn cancel_timer(
+    State(app_state): State<AppState>,
+    Path(id): Path<u64>
+) -> Result<Json<Timer>, http::StatusCode> {
+    let timer = app_state.timers.lock().unwrap().remove(id).ok_or(http::StatusCode::NOT_FOUND)?;
+    publish_timers(&app_state);
+    Ok(Json(timer))
+}
+
+/// The display's dismiss button: stops a ringing timer, which a display key may do.
+pub async fn stop_timer(
+    State(app_state): State<AppState>,
+    Path(id): Path<u64>
+) -> Result<Json<Timer>, http::StatusCode> {
+    let timer = app_state.timers.lock().unwrap().stop_ringing(id).ok_or(http::StatusCode::NOT_FOUND)?;
+    publish_timers(&app_state);
+    Ok(Json(timer))
+}
@@ -0,0 +1,154 @@
+use axum::body::Bytes;
+use dotenv::dotenv;
+use sha2::{Digest, Sha256};
+use std::{env, io::Write, path::PathBuf, time::SystemTime};
+
+use crate::chat;
+use crate::state::AppState;
+use crate::text_to_speech;
+use crate::voices;
+
+
+fn cache_dir() -> PathBuf {
+    dotenv().ok();
+    PathBuf::from(env::var("TTS_CACHE_DIR").unwrap_or_else(|_| "tts_cache".to_string()))
+}
+
+/// Upper bound on the cache folder in bytes; `0` turns caching off.
+fn max_bytes() -> u64 {
+    dotenv().ok();
+    env::var("TTS_CACHE_MAX_BYTES").ok()
+        .and_then(|b| b.parse().ok())
+        .unwrap_or(50_000_000)
+}
+
+/// Content address for a clip: any change to the text, voice or its settings is a new entry.
+pub fn cache_key(text: &str, voice_id: &str, voice_settings: &serde_json::Value) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(voice_id.as_bytes());
+    hasher.update([0]);
+    hasher.update(voice_settings.to_string().as_bytes());
+    hasher.update([0]);
+    hasher.update(text.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+fn clip_path(key: &str) -> PathBuf {
+    cache_dir().join(format!("{}.mp3", key))
+}
+
+pub async fn get(key: &str) -> Option<Bytes> {
+    if max_bytes() == 0 {
+        return None;
+    }
+    let path = clip_path(key);
+    let audio = tokio::fs::read(&path).await.ok()?;
+    // Bump the modified time so eviction drops the least recently used clips first.
+    // std has no async set_modified, so it runs off the async workers without holding up the reply.
+    tokio::task::spawn_blocking(move || {
+        if let Ok(file) = std::fs::File::options().append(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+    });
+    Some(Bytes::from(audio))
+}
+
+pub async fn put(key: &str, audio: &Bytes) {
+    let max_bytes = max_bytes();
+    if max_bytes == 0 || audio.is_empty() {
+        return;
+    }
+    let dir = cache_dir();
+    if let Err(err) = tokio::fs::create_dir_all(&dir).await {
+        tracing::error!("Failed to create TTS cache folder: {}", err);
+        return;
+    }
+
+    // Write to a uniquely named file, then rename, so a concurrent reader never sees a half-written clip
+    // and two puts of the same phrase (prewarm and a live request) don't write into one file.
+    let (tmp_dir, audio, path) = (dir.clone(), audio.clone(), clip_path(key));
+    let stored = tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+        let mut tmp = tempfile::NamedTempFile::new_in(tmp_dir)?;
+        tmp.write_all(&audio)?;
+        tmp.persist(path)?;
+        Ok(())
+    }).await.map_err(std::io::Error::other).and_then(|stored| stored);
+    if let Err(err) = stored {
+        tracing::error!("Failed to store TTS cache entry: {}", err);
+        return;
+    }
+
+    if let Err(err) = evict(dir, max_bytes).await {
+        tracing::warn!("Failed to evict TTS cache: {}", err);
+    }
+}
+
+async fn evict(dir: PathBuf, max_bytes: u64) -> std::io::Result<()> {
+    let mut clips = Vec::new();
+    let mut total = 0;
+    let mut entries = tokio::fs::read_dir(&dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        if !metadata.is_file() || entry.path().extension().is_none_or(|ext| ext != "mp3") {
+            continue;
+        }
+        total += metadata.len();
+        clips.push((metadata.modified()?, metadata.len(), entry.path()));
+    }
+
+    clips.sort_by_key(|(modified, _, _)| *modified);
+    for (_, size, path) in clips {
+        if total <= max_bytes {
+            break;
+        }
+        tokio::fs::remove_file(&path).await?;
+        total -= size;
+    }
+    Ok(())
+}
+
+/// Synthesizes the fixed system replies plus `TTS_PREWARM_PHRASES` so they play instantly.
+pub async fn prewarm(app_state: AppState) {
+    dotenv().ok();
+    if max_bytes() == 0 || env::var("ELEVENLABS_API_KEY").is_err() {
+        return;
+    }
+    let voice = voices::select(&app_state, None, None);
+
+    let extra = env::var("TTS_PREWARM_PHRASES").unwrap_or_default();
+    let phrases = chat::FIXED_REPLIES.iter()
+        .copied()
+        .chain(extra.split('|').map(|phrase| phrase.trim()).filter(|phrase| !phrase.is_empty()));
+
+    for phrase in phrases {
+        if let Err(err) = text_to_speech::synthesize(&app_state, phrase, &voice, None).await {
+            tracing::warn!("Failed to prewarm \"{}\": {}", phrase, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn concurrent_puts_of_one_phrase_leave_one_clip() {
+        let dir = tempfile::tempdir().unwrap();
+        // Nothing else under test reads TTS_CACHE_DIR.
+        env::set_var("TTS_CACHE_DIR", dir.path());
+        let key = cache_key("Okay, I won't.", "voice", &serde_json::json!({}));
+        let audio = Bytes::from(vec![7u8; 64 * 1024]);
+
+        let puts: Vec<_> = (0..8).map(|_| {
+            let (key, audio) = (key.clone(), audio.clone());
+            tokio::spawn(async move { put(&key, &audio).await })
+        }).collect();
+        for put in puts {
+            put.await.unwrap();
+        }
+
+        assert_eq!(get(&key).await, Some(audio));
+        let files: Vec<_> = std::fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(files.len(), 1, "temporary files were left behind");
+    }
+}
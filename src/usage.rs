@@ -0,0 +1,334 @@
+use axum::{Json, extract::{ConnectInfo, State}, middleware::Next, response::{IntoResponse, Response}};
+use chrono::NaiveDate;
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use std::{collections::{HashMap, VecDeque}, env, fs, net::SocketAddr, time::{Duration, Instant}};
+
+use crate::places;
+use crate::state::AppState;
+
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// What the paid upstream APIs have been asked for over some period.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Totals {
+    pub chat_requests: u64,
+    /// From OpenAI's reported usage, estimated from message length when it doesn't say.
+    pub chat_tokens: u64,
+    /// Characters sent to paid speech backends; cache hits and espeak are free.
+    pub tts_characters: u64,
+    /// Seconds of audio sent to OpenAI transcription; whisper.cpp is free.
+    pub stt_seconds: u64,
+    /// Requests turned away by a rate limit or a cap.
+    pub refused: u64,
+}
+
+impl Totals {
+    fn add(&mut self, other: &Totals) {
+        self.chat_requests += other.chat_requests;
+        self.chat_tokens += other.chat_tokens;
+        self.tts_characters += other.tts_characters;
+        self.stt_seconds += other.stt_seconds;
+        self.refused += other.refused;
+    }
+}
+
+/// Spend caps from the environment, 0 or unset means no cap.
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+pub struct Caps {
+    pub chat_daily_tokens: u64,
+    pub chat_monthly_tokens: u64,
+    pub tts_daily_characters: u64,
+    pub tts_monthly_characters: u64,
+    pub stt_daily_seconds: u64,
+    pub stt_monthly_seconds: u64,
+}
+
+fn env_number<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+impl Caps {
+    pub fn from_env() -> Caps {
+        dotenv().ok();
+        Caps {
+            chat_daily_tokens: env_number("CHAT_DAILY_TOKENS", 0),
+            chat_monthly_tokens: env_number("CHAT_MONTHLY_TOKENS", 0),
+            tts_daily_characters: env_number("TTS_DAILY_CHARACTERS", 0),
+            tts_monthly_characters: env_number("TTS_MONTHLY_CHARACTERS", 0),
+            stt_daily_seconds: env_number("STT_DAILY_SECONDS", 0),
+            stt_monthly_seconds: env_number("STT_MONTHLY_SECONDS", 0),
+        }
+    }
+}
+
+fn under(cap: u64, used: u64) -> bool {
+    cap == 0 || used < cap
+}
+
+fn fits(cap: u64, used: u64, more: u64) -> bool {
+    cap == 0 || used + more <= cap
+}
+
+/// Sliding one minute windows, per client and for the whole server.
+struct RateLimiter {
+    per_client: usize,
+    global: usize,
+    clients: HashMap<String, VecDeque<Instant>>,
+    all: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    fn from_env() -> RateLimiter {
+        dotenv().ok();
+        RateLimiter {
+            per_client: env_number("RATE_LIMIT_PER_MINUTE", 10),
+            global: env_number("RATE_LIMIT_GLOBAL_PER_MINUTE", 30),
+            clients: HashMap::new(),
+            all: VecDeque::new(),
+        }
+    }
+
+    /// Counts a request, or says how long to wait when over a limit.
+    fn check(&mut self, client: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let expire = |times: &mut VecDeque<Instant>| {
+            while times.front().is_some_and(|at| now.duration_since(*at) >= RATE_WINDOW) {
+                times.pop_front();
+            }
+        };
+        expire(&mut self.all);
+        self.clients.values_mut().for_each(expire);
+        self.clients.retain(|_, times| !times.is_empty());
+
+        let recent = self.clients.entry(client.to_string()).or_default();
+        for (limit, times) in [(self.per_client, &*recent), (self.global, &self.all)] {
+            if limit > 0 && times.len() >= limit {
+                let oldest = times.front().copied().unwrap_or(now);
+                return Err(RATE_WINDOW.saturating_sub(now.duration_since(oldest)));
+            }
+        }
+        recent.push_back(now);
+        self.all.push_back(now);
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize, Default)]
+struct StoredUsage {
+    day: Option<NaiveDate>,
+    today: Totals,
+    month: Option<String>,
+    this_month: Totals,
+}
+
+fn usage_path() -> String {
+    dotenv().ok();
+    env::var("USAGE_PATH").unwrap_or_else(|_| "usage.json".to_string())
+}
+
+fn today() -> NaiveDate {
+    places::home_now().date_naive()
+}
+
+/// Running totals for today and this month, kept in `USAGE_PATH` so caps survive restarts.
+pub struct UsageTracker {
+    stored: StoredUsage,
+    caps: Caps,
+    limiter: RateLimiter,
+}
+
+impl UsageTracker {
+    pub fn load() -> UsageTracker {
+        let path = usage_path();
+        let stored = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+                tracing::warn!("Error parsing usage in {}: {}", path, err);
+                StoredUsage::default()
+            }),
+            Err(_) => StoredUsage::default(),
+        };
+        UsageTracker { stored, caps: Caps::from_env(), limiter: RateLimiter::from_env() }
+    }
+
+    fn save(&self) {
+        let result = serde_json::to_string_pretty(&self.stored)
+            .map_err(std::io::Error::from)
+            .and_then(|contents| fs::write(usage_path(), contents));
+        if let Err(err) = result {
+            tracing::error!("Failed to save usage: {}", err);
+        }
+    }
+
+    /// Starts new totals when the day or month has turned over.
+    fn roll_over(&mut self) {
+        let day = today();
+        let month = day.format("%Y-%m").to_string();
+        if self.stored.day != Some(day) {
+            self.stored.day = Some(day);
+            self.stored.today = Totals::default();
+        }
+        if self.stored.month.as_deref() != Some(month.as_str()) {
+            self.stored.month = Some(month);
+            self.stored.this_month = Totals::default();
+        }
+    }
+
+    fn record(&mut self, usage: Totals) {
+        self.roll_over();
+        self.stored.today.add(&usage);
+        self.stored.this_month.add(&usage);
+        self.save();
+    }
+
+    pub fn chat_allowed(&mut self) -> bool {
+        self.roll_over();
+        under(self.caps.chat_daily_tokens, self.stored.today.chat_tokens)
+            && under(self.caps.chat_monthly_tokens, self.stored.this_month.chat_tokens)
+    }
+
+    pub fn tts_allowed(&mut self, characters: usize) -> bool {
+        self.roll_over();
+        let characters = characters as u64;
+        fits(self.caps.tts_daily_characters, self.stored.today.tts_characters, characters)
+            && fits(self.caps.tts_monthly_characters, self.stored.this_month.tts_characters, characters)
+    }
+
+    pub fn stt_allowed(&mut self) -> bool {
+        self.roll_over();
+        under(self.caps.stt_daily_seconds, self.stored.today.stt_seconds)
+            && under(self.caps.stt_monthly_seconds, self.stored.this_month.stt_seconds)
+    }
+}
+
+/// What paid backends fail with once a cap is reached, so callers can fall back instead of giving up.
+#[derive(Debug, Clone, Copy)]
+pub struct OverBudget(pub &'static str);
+
+impl std::fmt::Display for OverBudget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} budget used up", self.0)
+    }
+}
+
+impl std::error::Error for OverBudget {}
+
+/// Rough count for when a response carries no usage, about four characters a token.
+pub fn estimate_tokens(text: &str) -> u64 {
+    (text.len() as u64).div_ceil(4)
+}
+
+pub fn chat_allowed(app_state: &AppState) -> bool {
+    let allowed = app_state.usage.lock().unwrap().chat_allowed();
+    if !allowed {
+        record_refused(app_state);
+    }
+    allowed
+}
+
+pub fn tts_allowed(app_state: &AppState, text: &str) -> bool {
+    app_state.usage.lock().unwrap().tts_allowed(text.chars().count())
+}
+
+pub fn stt_allowed(app_state: &AppState) -> bool {
+    let allowed = app_state.usage.lock().unwrap().stt_allowed();
+    if !allowed {
+        record_refused(app_state);
+    }
+    allowed
+}
+
+/// Counts one OpenAI chat call from its response body, `messages` is the estimate's fallback.
+pub fn record_chat(app_state: &AppState, response: &serde_json::Value, messages: &[serde_json::Value]) {
+    let tokens = response["usage"]["total_tokens"].as_u64().unwrap_or_else(|| {
+        let sent: usize = messages.iter().map(|message| message.to_string().len()).sum();
+        (sent as u64).div_ceil(4) + estimate_tokens(&response["choices"].to_string())
+    });
+    app_state.usage.lock().unwrap().record(Totals { chat_requests: 1, chat_tokens: tokens, ..Totals::default() });
+}
+
+pub fn record_tts(app_state: &AppState, text: &str) {
+    app_state.usage.lock().unwrap().record(Totals { tts_characters: text.chars().count() as u64, ..Totals::default() });
+}
+
+/// Counts one paid transcription, billed by the started second.
+pub fn record_stt(app_state: &AppState, seconds: f64) {
+    app_state.usage.lock().unwrap().record(Totals { stt_seconds: seconds.max(0.0).ceil() as u64, ..Totals::default() });
+}
+
+fn record_refused(app_state: &AppState) {
+    app_state.usage.lock().unwrap().record(Totals { refused: 1, ..Totals::default() });
+}
+
+/// Routes that end up calling a paid API.
+fn is_paid(path: &str) -> bool {
+    let section = path.trim_start_matches('/').split('/').next().unwrap_or_default();
+    matches!(section, "chat" | "transcribe" | "listen" | "announce" | "briefing")
+}
+
+/// Clients are told apart by API key name, or by address when auth is off.
+pub fn client_name(key: Option<&crate::auth::ClientName>, address: Option<&ConnectInfo<SocketAddr>>) -> String {
+    key.map(|client| client.0.clone())
+        .or_else(|| address.map(|info| info.0.ip().to_string()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Counts one paid request against the rate limits, or says how long to wait.
+/// Long-lived connections like `/listen` call this per turn, not just when they open.
+pub fn check_rate(app_state: &AppState, client: &str) -> Result<(), Duration> {
+    let checked = app_state.usage.lock().unwrap().limiter.check(client);
+    if checked.is_err() {
+        record_refused(app_state);
+    }
+    checked
+}
+
+/// Middleware: per-client and global request limits on the paid routes.
+pub async fn rate_limit<B>(
+    State(app_state): State<AppState>,
+    request: http::Request<B>,
+    next: Next<B>
+) -> Response {
+    if !is_paid(request.uri().path()) || request.method() == http::Method::OPTIONS {
+        return next.run(request).await;
+    }
+    let client = client_name(request.extensions().get(), request.extensions().get());
+    match check_rate(&app_state, &client) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            let seconds = retry_after.as_secs().max(1);
+            (
+                http::StatusCode::TOO_MANY_REQUESTS,
+                [(http::header::RETRY_AFTER, seconds.to_string())],
+                format!("Too many requests, try again in {} seconds", seconds)
+            ).into_response()
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct UsageReport {
+    pub day: Option<NaiveDate>,
+    pub today: Totals,
+    pub month: Option<String>,
+    pub this_month: Totals,
+    pub caps: Caps,
+    pub rate_limit_per_minute: usize,
+    pub rate_limit_global_per_minute: usize,
+}
+
+pub async fn get_usage(State(app_state): State<AppState>) -> Json<UsageReport> {
+    let mut usage = app_state.usage.lock().unwrap();
+    usage.roll_over();
+    Json(UsageReport {
+        day: usage.stored.day,
+        today: usage.stored.today.clone(),
+        month: usage.stored.month.clone(),
+        this_month: usage.stored.this_month.clone(),
+        caps: usage.caps,
+        rate_limit_per_minute: usage.limiter.per_client,
+        rate_limit_global_per_minute: usage.limiter.global,
+    })
+}
@@ -0,0 +1,187 @@
+use axum::Json;
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, env, fs};
+
+use crate::state::AppState;
+
+
+/// How the mirror sounds: which voice, which model and how it is delivered.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct VoiceProfile {
+    /// ElevenLabs voice.
+    #[serde(default)]
+    pub voice_id: String,
+    #[serde(default)]
+    pub model_id: Option<String>,
+    #[serde(default)]
+    pub openai_voice: Option<String>,
+    #[serde(default)]
+    pub espeak_voice: Option<String>,
+    #[serde(default = "default_stability")]
+    pub stability: f64,
+    #[serde(default = "default_similarity_boost")]
+    pub similarity_boost: f64,
+    #[serde(default = "default_speaking_rate")]
+    pub speaking_rate: f64,
+}
+
+fn default_stability() -> f64 { 0.5 }
+fn default_similarity_boost() -> f64 { 0.7 }
+fn default_speaking_rate() -> f64 { 1.0 }
+
+/// Optional delivery hints the model may attach to a reply.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Prosody {
+    #[serde(default)]
+    pub pauses: Vec<Pause>,
+    #[serde(default)]
+    pub emphasis: Vec<String>,
+    #[serde(default)]
+    pub rate: Option<f64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Pause {
+    pub after: String,
+    pub seconds: f64,
+}
+
+/// The first `phrase` outside any `<...>` markup, so markers already placed are never matched into.
+fn find_outside_tags(text: &str, phrase: &str) -> Option<usize> {
+    text.match_indices(phrase).map(|(position, _)| position).find(|&position| {
+        let before = &text[..position];
+        before.rfind('<').is_none_or(|open| before[open..].contains('>'))
+    })
+}
+
+impl Prosody {
+    /// Places a backend-specific break marker after each phrase named in `pauses`.
+    pub fn insert_breaks(&self, text: &str, render_break: impl Fn(f64) -> String) -> String {
+        let mut text = text.to_string();
+        for pause in &self.pauses {
+            if pause.after.is_empty() {
+                continue;
+            }
+            if let Some(position) = find_outside_tags(&text, &pause.after) {
+                let end = position + pause.after.len();
+                text.insert_str(end, &format!(" {}", render_break(pause.seconds.clamp(0.0, 3.0))));
+            }
+        }
+        text
+    }
+
+    /// Wraps the first occurrence of each emphasized phrase with a backend-specific marker.
+    pub fn wrap_emphasis(&self, text: &str, render: impl Fn(&str) -> String) -> String {
+        let mut text = text.to_string();
+        for phrase in &self.emphasis {
+            if phrase.is_empty() {
+                continue;
+            }
+            if let Some(position) = find_outside_tags(&text, phrase) {
+                let end = position + phrase.len();
+                text.replace_range(position..end, &render(phrase));
+            }
+        }
+        text
+    }
+}
+
+/// Reads `VOICE_PROFILES_PATH` (default voices.json). A "default" profile built from `VOICE_ID` always exists.
+pub fn load_profiles() -> HashMap<String, VoiceProfile> {
+    dotenv().ok();
+    let path = env::var("VOICE_PROFILES_PATH").unwrap_or_else(|_| "voices.json".to_string());
+    let mut profiles: HashMap<String, VoiceProfile> = match fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+            tracing::warn!("Error parsing voice profiles in {}: {}", path, err);
+            HashMap::new()
+        }),
+        Err(_) => HashMap::new(),
+    };
+
+    profiles.entry("default".to_string()).or_insert_with(|| VoiceProfile {
+        voice_id: env::var("VOICE_ID").unwrap_or_default(),
+        model_id: None,
+        openai_voice: None,
+        espeak_voice: None,
+        stability: default_stability(),
+        similarity_boost: default_similarity_boost(),
+        speaking_rate: default_speaking_rate(),
+    });
+    profiles
+}
+
+fn default_profile_name() -> String {
+    dotenv().ok();
+    env::var("DEFAULT_VOICE").unwrap_or_else(|_| "default".to_string())
+}
+
+/// Picks the voice for a reply: the request's own choice, then the one this user picked by saying so,
+/// then their profile's, then the default.
+pub fn select(app_state: &AppState, requested: Option<&str>, user: Option<&str>) -> VoiceProfile {
+    let profile = crate::profiles::resolve(app_state, user);
+    let session = app_state.session_voice.lock().unwrap().get(&profile.id).cloned();
+    let user_voice = profile.voice;
+    let mut profiles = load_profiles();
+    [requested.map(str::to_string), session, user_voice, Some(default_profile_name())]
+        .into_iter()
+        .flatten()
+        .find_map(|name| profiles.remove(&name))
+        .unwrap_or_else(|| profiles.remove("default").expect("load_profiles always adds a default"))
+}
+
+pub async fn list_voices() -> Json<Vec<String>> {
+    let mut names: Vec<String> = load_profiles().into_keys().collect();
+    names.sort();
+    Json(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prosody(pauses: &[(&str, f64)], emphasis: &[&str]) -> Prosody {
+        Prosody {
+            pauses: pauses.iter().map(|(after, seconds)| Pause { after: after.to_string(), seconds: *seconds }).collect(),
+            emphasis: emphasis.iter().map(|word| word.to_string()).collect(),
+            rate: None,
+        }
+    }
+
+    fn emphasize(word: &str) -> String {
+        format!("<emphasis>{}</emphasis>", word)
+    }
+
+    fn pause(seconds: f64) -> String {
+        format!("<break time=\"{}ms\"/>", (seconds * 1000.0) as u64)
+    }
+
+    #[test]
+    fn emphasis_skips_break_markup() {
+        let prosody = prosody(&[("Done.", 0.5)], &["time", "ms"]);
+        let text = prosody.insert_breaks("Done. It's time to go, 5 ms left.", pause);
+        let text = prosody.wrap_emphasis(&text, emphasize);
+        assert_eq!(text, "Done. <break time=\"500ms\"/> It's <emphasis>time</emphasis> to go, 5 <emphasis>ms</emphasis> left.");
+    }
+
+    #[test]
+    fn breaks_skip_emphasis_markup() {
+        let prosody = prosody(&[("emphasis", 1.0)], &["Stop"]);
+        let text = prosody.wrap_emphasis("Stop. No emphasis needed.", emphasize);
+        let text = prosody.insert_breaks(&text, pause);
+        assert_eq!(text, "<emphasis>Stop</emphasis>. No emphasis <break time=\"1000ms\"/> needed.");
+    }
+
+    #[test]
+    fn breaks_are_clamped() {
+        let text = prosody(&[("Wait", 10.0)], &[]).insert_breaks("Wait for it", pause);
+        assert_eq!(text, "Wait <break time=\"3000ms\"/> for it");
+    }
+
+    #[test]
+    fn missing_phrases_change_nothing() {
+        let prosody = prosody(&[("nowhere", 1.0), ("", 1.0)], &["absent", ""]);
+        let text = prosody.wrap_emphasis(&prosody.insert_breaks("Plain text.", pause), emphasize);
+        assert_eq!(text, "Plain text.");
+    }
+}
@@ -0,0 +1,423 @@
+use axum::{Extension, Json, body::Bytes, extract::{ConnectInfo, State, ws::{Message, WebSocket, WebSocketUpgrade}}, response::Response};
+use dotenv::dotenv;
+use std::{collections::VecDeque, env, io::Cursor, net::SocketAddr, time::{Duration, Instant}};
+
+use crate::announce;
+use crate::auth::ClientName;
+use crate::chat;
+use crate::push::{self, MirrorEvent};
+use crate::speech_to_text::SttBackend;
+use crate::state::AppState;
+use crate::text_to_speech;
+use crate::usage;
+
+/// Audio arriving on `/listen` must be 16 kHz mono signed 16-bit little-endian PCM.
+pub const SAMPLE_RATE: u32 = 16_000;
+const FRAME_SAMPLES: usize = 480; // 30 ms
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    env::var(key).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+fn frames_for_ms(ms: u64) -> usize {
+    (ms as usize * SAMPLE_RATE as usize / 1000).div_ceil(FRAME_SAMPLES).max(1)
+}
+
+/// Splits a continuous sample stream into utterances using frame energy.
+pub struct VoiceActivityDetector {
+    threshold: f64,
+    silence_frames: usize,
+    min_speech_frames: usize,
+    max_samples: usize,
+    preroll_frames: usize,
+    pending: Vec<i16>,
+    preroll: VecDeque<Vec<i16>>,
+    utterance: Vec<i16>,
+    speaking: bool,
+    quiet_frames: usize,
+    loud_frames: usize,
+}
+
+impl VoiceActivityDetector {
+    pub fn new(threshold: f64, silence_ms: u64, max_utterance_ms: u64) -> VoiceActivityDetector {
+        VoiceActivityDetector {
+            threshold,
+            silence_frames: frames_for_ms(silence_ms),
+            min_speech_frames: frames_for_ms(150),
+            max_samples: max_utterance_ms as usize * SAMPLE_RATE as usize / 1000,
+            preroll_frames: frames_for_ms(300),
+            pending: Vec::new(),
+            preroll: VecDeque::new(),
+            utterance: Vec::new(),
+            speaking: false,
+            quiet_frames: 0,
+            loud_frames: 0,
+        }
+    }
+
+    pub fn from_env() -> VoiceActivityDetector {
+        dotenv().ok();
+        VoiceActivityDetector::new(
+            env_or("VAD_THRESHOLD", 500.0),
+            env_or("VAD_SILENCE_MS", 800),
+            env_or("VAD_MAX_UTTERANCE_MS", 15_000),
+        )
+    }
+
+    /// Feeds samples in and returns every utterance that finished within them.
+    pub fn push(&mut self, samples: &[i16]) -> Vec<Vec<i16>> {
+        self.pending.extend_from_slice(samples);
+        let mut finished = Vec::new();
+        while self.pending.len() >= FRAME_SAMPLES {
+            let frame: Vec<i16> = self.pending.drain(..FRAME_SAMPLES).collect();
+            if let Some(utterance) = self.push_frame(frame) {
+                finished.push(utterance);
+            }
+        }
+        finished
+    }
+
+    fn push_frame(&mut self, frame: Vec<i16>) -> Option<Vec<i16>> {
+        let loud = rms(&frame) >= self.threshold;
+
+        if !self.speaking {
+            if loud {
+                // Keep a little audio from before the onset so first syllables aren't clipped.
+                self.speaking = true;
+                self.utterance = self.preroll.drain(..).flatten().collect();
+                self.utterance.extend(frame);
+                self.quiet_frames = 0;
+                self.loud_frames = 1;
+            } else {
+                self.preroll.push_back(frame);
+                if self.preroll.len() > self.preroll_frames {
+                    self.preroll.pop_front();
+                }
+            }
+            return None;
+        }
+
+        self.utterance.extend(frame);
+        if loud {
+            self.quiet_frames = 0;
+            self.loud_frames += 1;
+        } else {
+            self.quiet_frames += 1;
+        }
+
+        if self.quiet_frames < self.silence_frames && self.utterance.len() < self.max_samples {
+            return None;
+        }
+
+        self.speaking = false;
+        self.quiet_frames = 0;
+        let utterance = std::mem::take(&mut self.utterance);
+        // Clicks and bumps are loud but short; only hand real speech to the transcriber.
+        if self.loud_frames >= self.min_speech_frames {
+            Some(utterance)
+        } else {
+            None
+        }
+    }
+}
+
+fn rms(frame: &[i16]) -> f64 {
+    let sum: f64 = frame.iter().map(|s| (*s as f64) * (*s as f64)).sum();
+    (sum / frame.len() as f64).sqrt()
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Heard {
+    Nothing,
+    WakeWord,
+    Command(String),
+}
+
+/// Decides whether a transcript woke the mirror and what the command was.
+pub struct WakeWordListener {
+    phrase: String,
+    command_timeout: Duration,
+    awaiting_since: Option<Instant>,
+}
+
+impl WakeWordListener {
+    pub fn new(phrase: &str, command_timeout: Duration) -> WakeWordListener {
+        WakeWordListener {
+            phrase: normalize(phrase),
+            command_timeout,
+            awaiting_since: None,
+        }
+    }
+
+    pub fn from_env() -> WakeWordListener {
+        dotenv().ok();
+        WakeWordListener::new(
+            &env::var("WAKE_PHRASE").unwrap_or_else(|_| "carter".to_string()),
+            Duration::from_secs(env_or("WAKE_COMMAND_TIMEOUT_SECONDS", 8)),
+        )
+    }
+
+    pub fn hear(&mut self, transcript: &str, now: Instant) -> Heard {
+        let normalized = normalize(transcript);
+
+        if let Some(since) = self.awaiting_since.take() {
+            if now.duration_since(since) <= self.command_timeout && !normalized.is_empty() {
+                return Heard::Command(normalized);
+            }
+        }
+
+        let padded = format!(" {} ", normalized);
+        let needle = format!(" {} ", self.phrase);
+        match padded.find(&needle) {
+            Some(position) => {
+                // "carter what's the weather" carries its command in the same utterance.
+                let rest = padded[position + needle.len()..].trim();
+                if rest.is_empty() {
+                    self.awaiting_since = Some(now);
+                    Heard::WakeWord
+                } else {
+                    Heard::Command(rest.to_string())
+                }
+            }
+            None => Heard::Nothing,
+        }
+    }
+}
+
+fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '\'' { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn pcm_to_wav(samples: &[i16]) -> Result<Vec<u8>, hound::Error> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut wav = Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut wav, spec)?;
+    for sample in samples {
+        writer.write_sample(*sample)?;
+    }
+    writer.finalize()?;
+    Ok(wav.into_inner())
+}
+
+/// Reads a 16 kHz mono 16-bit WAV into samples.
+pub fn wav_to_pcm(wav: &[u8]) -> Result<Vec<i16>, hound::Error> {
+    let reader = hound::WavReader::new(Cursor::new(wav))?;
+    let spec = reader.spec();
+    if spec.channels != 1 || spec.sample_rate != SAMPLE_RATE || spec.bits_per_sample != 16 {
+        return Err(hound::Error::Unsupported);
+    }
+    reader.into_samples::<i16>().collect()
+}
+
+pub async fn listen(
+    ws: WebSocketUpgrade,
+    State(app_state): State<AppState>,
+    key: Option<Extension<ClientName>>,
+    address: Option<ConnectInfo<SocketAddr>>
+) -> Response {
+    let client = usage::client_name(key.as_deref(), address.as_ref());
+    ws.on_upgrade(move |socket| handle_socket(socket, app_state, client))
+}
+
+async fn send_json(socket: &mut WebSocket, value: serde_json::Value) -> bool {
+    socket.send(Message::Text(value.to_string())).await.is_ok()
+}
+
+async fn handle_socket(mut socket: WebSocket, app_state: AppState, client: String) {
+    let Some(backend) = SttBackend::from_env() else {
+        tracing::warn!("No STT_BACKEND configured, closing /listen");
+        send_json(&mut socket, serde_json::json!({ "type": "error", "text": "Speech to text is not configured." })).await;
+        return;
+    };
+    // The wake phrase is found in transcripts, so everything said near the microphone gets transcribed,
+    // not just commands. Sending all of that to a cloud service has to be asked for.
+    if !backend.is_local() && !env::var("LISTEN_REMOTE_STT").is_ok_and(|value| value == "true") {
+        tracing::warn!("STT_BACKEND sends audio off this machine, closing /listen unless LISTEN_REMOTE_STT=true");
+        send_json(&mut socket, serde_json::json!({ "type": "error", "text": "Listening needs a local speech to text backend." })).await;
+        return;
+    }
+    let mut vad = VoiceActivityDetector::from_env();
+    let mut listener = WakeWordListener::from_env();
+
+    while let Some(Ok(message)) = socket.recv().await {
+        let bytes = match message {
+            Message::Binary(bytes) => bytes,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        // A whole WAV file (e.g. a recorded fixture) is accepted as well as raw PCM frames.
+        let samples: Vec<i16> = if bytes.starts_with(b"RIFF") {
+            match wav_to_pcm(&bytes) {
+                Ok(samples) => samples,
+                Err(err) => {
+                    tracing::warn!("Rejected WAV on /listen: {}", err);
+                    continue;
+                }
+            }
+        } else {
+            bytes.chunks_exact(2)
+                .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+                .collect()
+        };
+
+        for utterance in vad.push(&samples) {
+            let wav = match pcm_to_wav(&utterance) {
+                Ok(wav) => wav,
+                Err(err) => {
+                    tracing::warn!("Failed to encode utterance: {}", err);
+                    continue;
+                }
+            };
+            let transcript = match backend.transcribe(&app_state, Bytes::from(wav), "wav").await {
+                Ok(text) => text,
+                Err(err) => {
+                    tracing::warn!("Failed to transcribe utterance: {}", err);
+                    continue;
+                }
+            };
+
+            let sent = match listener.hear(&transcript, Instant::now()) {
+                Heard::Nothing => true,
+                Heard::WakeWord => {
+                    // Announcements wait while the command is spoken.
+                    announce::hold(&app_state, listener.command_timeout);
+                    send_json(&mut socket, serde_json::json!({ "type": "wake_word" })).await
+                },
+                Heard::Command(command) => {
+                    tracing::debug!(command = %crate::logging::content(&command), "Command received");
+                    send_json(&mut socket, serde_json::json!({ "type": "command", "text": command })).await;
+                    // The socket was only checked when it opened, every command is a paid request of its own.
+                    if let Err(retry_after) = usage::check_rate(&app_state, &client) {
+                        let text = format!("Too many requests, try again in {} seconds", retry_after.as_secs().max(1));
+                        if !send_json(&mut socket, serde_json::json!({ "type": "error", "text": text })).await {
+                            return;
+                        }
+                        continue;
+                    }
+                    if !usage::chat_allowed(&app_state) {
+                        if !send_json(&mut socket, serde_json::json!({ "type": "error", "text": chat::BUDGET_MSG })).await {
+                            return;
+                        }
+                        continue;
+                    }
+                    let _hold = announce::conversation_started(&app_state);
+
+                    let reply = chat::handle_chat_request(
+                        app_state.clone(),
+                        Json(chat::ChatRequest{message: command, user: None, voice: None})
+                    ).await;
+                    // Speech checks its own budget per reply and drops to espeak once it runs out.
+                    if let Err(err) = text_to_speech::speak(&app_state, &reply.text).await {
+                        tracing::warn!("Failed to synthesize reply: {}", err);
+                        push::publish(&app_state, MirrorEvent::Alert { text: reply.text.clone() });
+                    }
+                    send_json(&mut socket, serde_json::json!({ "type": "reply", "text": reply.text })).await
+                }
+            };
+            if !sent {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(wav: &[u8]) -> Vec<i16> {
+        wav_to_pcm(wav).unwrap()
+    }
+
+    /// Runs samples through the detector in 20 ms chunks, the way `/listen` receives them.
+    fn utterances(samples: &[i16]) -> Vec<Vec<i16>> {
+        let mut vad = VoiceActivityDetector::new(500.0, 800, 15_000);
+        samples.chunks(320).flat_map(|chunk| vad.push(chunk)).collect()
+    }
+
+    fn seconds(samples: &[i16]) -> f64 {
+        samples.len() as f64 / SAMPLE_RATE as f64
+    }
+
+    #[test]
+    fn silence_is_not_an_utterance() {
+        assert!(utterances(&fixture(include_bytes!("../tests/fixtures/silence.wav"))).is_empty());
+    }
+
+    #[test]
+    fn speech_is_one_utterance_with_preroll() {
+        let found = utterances(&fixture(include_bytes!("../tests/fixtures/speech.wav")));
+        assert_eq!(found.len(), 1);
+        // One second of speech, plus preroll before it and the silence that ended it.
+        let length = seconds(&found[0]);
+        assert!((2.0..2.2).contains(&length), "utterance was {}s", length);
+    }
+
+    #[test]
+    fn a_click_is_not_speech() {
+        let mut samples = vec![0; SAMPLE_RATE as usize];
+        samples[8000..8100].fill(20_000);
+        assert!(utterances(&samples).is_empty());
+    }
+
+    #[test]
+    fn long_speech_is_cut_at_the_limit() {
+        let tone: Vec<i16> = (0..SAMPLE_RATE as usize * 3).map(|i| if i % 40 < 20 { 3000 } else { -3000 }).collect();
+        let mut vad = VoiceActivityDetector::new(500.0, 800, 1_000);
+        let found = vad.push(&tone);
+        assert!(found.len() >= 2);
+        // Cut on the first frame past the limit.
+        assert!(found.iter().all(|utterance| utterance.len() <= SAMPLE_RATE as usize + FRAME_SAMPLES));
+    }
+
+    #[test]
+    fn wake_phrase_then_command() {
+        // The fixture is the wake phrase, a pause, then the command; transcripts stand in for the STT backend.
+        let found = utterances(&fixture(include_bytes!("../tests/fixtures/wake_phrase.wav")));
+        assert_eq!(found.len(), 2);
+        let mut listener = WakeWordListener::new("Carter", Duration::from_secs(8));
+        let start = Instant::now();
+        let heard: Vec<Heard> = ["Carter.", "What's the weather?"].iter()
+            .enumerate()
+            .map(|(i, transcript)| listener.hear(transcript, start + Duration::from_secs(2 * i as u64)))
+            .collect();
+        assert_eq!(heard, [Heard::WakeWord, Heard::Command("what's the weather".to_string())]);
+    }
+
+    #[test]
+    fn command_in_the_same_breath() {
+        let mut listener = WakeWordListener::new("carter", Duration::from_secs(8));
+        assert_eq!(listener.hear("Hey Carter, set a timer", Instant::now()), Heard::Command("set a timer".to_string()));
+    }
+
+    #[test]
+    fn ignores_speech_without_the_wake_phrase() {
+        let mut listener = WakeWordListener::new("carter", Duration::from_secs(8));
+        assert_eq!(listener.hear("the cartridge is empty", Instant::now()), Heard::Nothing);
+    }
+
+    #[test]
+    fn the_wake_phrase_times_out() {
+        let mut listener = WakeWordListener::new("carter", Duration::from_secs(8));
+        let start = Instant::now();
+        assert_eq!(listener.hear("carter", start), Heard::WakeWord);
+        assert_eq!(listener.hear("what's the weather", start + Duration::from_secs(9)), Heard::Nothing);
+    }
+
+    #[test]
+    fn wav_round_trip() {
+        let samples = fixture(include_bytes!("../tests/fixtures/speech.wav"));
+        assert_eq!(wav_to_pcm(&pcm_to_wav(&samples).unwrap()).unwrap(), samples);
+    }
+}
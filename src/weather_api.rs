@@ -1,44 +1,229 @@
 use serde::{Deserialize, Serialize};
-use reqwest::Error;
 use std::env;
 use dotenv::dotenv;
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct WeatherResponse{
     pub location: LocationResponse,
     pub current: CurrentResponse,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct LocationResponse{
     pub name: String,
     pub region: String,
     pub country: String,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct CurrentResponse{
     pub temp_f: f64,
+    pub temp_c: f64,
     pub condition: Condition,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Condition{
     pub text: String,
     pub icon: String,
 }
 
 
-pub async fn get_weather(location: &str)->Result<WeatherResponse, Error> {
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ForecastResponse{
+    pub location: LocationResponse,
+    pub forecast: Forecast,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Forecast{
+    pub forecastday: Vec<ForecastDay>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ForecastDay{
+    pub date: String,
+    pub day: DaySummary,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DaySummary{
+    pub maxtemp_f: f64,
+    pub maxtemp_c: f64,
+    pub mintemp_f: f64,
+    pub mintemp_c: f64,
+    #[serde(default)]
+    pub daily_chance_of_rain: f64,
+    pub condition: Condition,
+}
+
+/// One match from weatherapi's location search.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct LocationCandidate{
+    pub name: String,
+    pub region: String,
+    pub country: String,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl LocationCandidate {
+    pub fn label(&self) -> String {
+        [self.name.as_str(), self.region.as_str(), self.country.as_str()]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub fn query(&self) -> String {
+        format!("{},{}", self.lat, self.lon)
+    }
+}
+
+/// Outcome of turning what someone said into something weatherapi can look up.
+#[derive(Debug)]
+pub enum Geocoded {
+    Found(String),
+    Ambiguous(Vec<LocationCandidate>),
+    NotFound,
+}
+
+pub type WeatherError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Goes in the query string, so every request below strips the URL from its errors.
+fn api_key() -> Result<String, WeatherError> {
     dotenv().ok();
-    let api_key = env::var("WEATHER_API_KEY").expect("Missing WEATHER_API_KEY in .env");
-    let request_url = format!("http://api.weatherapi.com/v1/current.json?key={api_key}&q={location}&aqi=no");
+    env::var("WEATHER_API_KEY").ok()
+        .filter(|key| !key.is_empty())
+        .ok_or_else(|| "WEATHER_API_KEY is not set".into())
+}
+
+fn is_coordinates(location: &str) -> bool {
+    let mut parts = location.split(',');
+    matches!(
+        (parts.next(), parts.next(), parts.next()),
+        (Some(lat), Some(lon), None) if lat.trim().parse::<f64>().is_ok() && lon.trim().parse::<f64>().is_ok()
+    )
+}
+
+pub async fn search_locations(query: &str) -> Result<Vec<LocationCandidate>, WeatherError> {
+    let key = api_key()?;
     let client = reqwest::Client::new();
-    let response: WeatherResponse = client.get(request_url).send().await?.json().await?;
+    let response = async {
+        client.get("http://api.weatherapi.com/v1/search.json")
+            .query(&[("key", key.as_str()), ("q", query.trim())])
+            .send()
+            .await?
+            .json()
+            .await
+    }.await.map_err(reqwest::Error::without_url)?;
     Ok(response)
 }
 
-pub async fn get_weather_json(axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>) -> axum::Json<WeatherResponse> {
-    let location: &str = params.get("location").unwrap();
-    axum::Json(get_weather(&location).await.unwrap())
+/// Resolves saved places and coordinates locally, everything else through the location search.
+/// Distinct matches for a bare name like "Springfield" come back as `Ambiguous`.
+pub async fn geocode(location: &str) -> Result<Geocoded, WeatherError> {
+    let location = location.trim();
+    if location.is_empty() {
+        return Ok(Geocoded::NotFound);
+    }
+    if let Some(place) = crate::places::find(location) {
+        return Ok(Geocoded::Found(place.query()));
+    }
+    if is_coordinates(location) {
+        return Ok(Geocoded::Found(location.to_string()));
+    }
+
+    let mut candidates = search_locations(location).await?;
+    // The search can list a place more than once, and not always next to itself.
+    let mut seen = std::collections::HashSet::new();
+    candidates.retain(|candidate| seen.insert(candidate.label()));
+    // "Springfield, IL" is already qualified, so trust the search's first pick.
+    let qualified = location.contains(',');
+    match candidates.len() {
+        0 => Ok(Geocoded::NotFound),
+        1 => Ok(Geocoded::Found(candidates[0].query())),
+        _ if qualified => Ok(Geocoded::Found(candidates[0].query())),
+        _ => Ok(Geocoded::Ambiguous(candidates)),
+    }
+}
+
+/// Weather for an already geocoded query (coordinates or a name weatherapi understands).
+pub async fn get_weather(location: &str)->Result<WeatherResponse, WeatherError> {
+    let location = crate::places::to_query(location);
+    let key = api_key()?;
+    let client = reqwest::Client::new();
+    let response = async {
+        client.get("http://api.weatherapi.com/v1/current.json")
+            .query(&[("key", key.as_str()), ("q", location.as_str()), ("aqi", "no")])
+            .send()
+            .await?
+            .json()
+            .await
+    }.await.map_err(reqwest::Error::without_url)?;
+    Ok(response)
+}
+
+pub async fn get_forecast(location: &str, days: u8)->Result<ForecastResponse, WeatherError> {
+    let location = crate::places::to_query(location);
+    let key = api_key()?;
+    let client = reqwest::Client::new();
+    let response = async {
+        client.get("http://api.weatherapi.com/v1/forecast.json")
+            .query(&[("key", key.as_str()), ("q", location.as_str()), ("days", &days.to_string()), ("aqi", "no"), ("alerts", "no")])
+            .send()
+            .await?
+            .json()
+            .await
+    }.await.map_err(reqwest::Error::without_url)?;
+    Ok(response)
+}
+
+pub async fn get_weather_json(
+    axum::extract::State(app_state): axum::extract::State<crate::state::AppState>,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>
+) -> Result<axum::Json<WeatherResponse>, http::StatusCode> {
+    let requested = params.get("location").cloned();
+    let for_display = requested.is_none();
+    let location = requested
+        .or(crate::profiles::resolve(&app_state, None).location)
+        .or(crate::places::home().map(|home| home.query()))
+        .ok_or(http::StatusCode::BAD_REQUEST)?;
+    let query = match geocode(&location).await {
+        Ok(Geocoded::Found(query)) => query,
+        // The display can't ask which one, so it shows the best match.
+        Ok(Geocoded::Ambiguous(candidates)) => candidates[0].query(),
+        Ok(Geocoded::NotFound) => return Err(http::StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::warn!(location = %crate::logging::content(&location), "Failed to geocode: {}", err);
+            return Err(http::StatusCode::BAD_GATEWAY);
+        }
+    };
+    // The display asks without a location; remember what it shows so pushed refreshes use the same place.
+    // Anyone asking about somewhere else mustn't move the mirror's weather.
+    if for_display {
+        *app_state.display_location.lock().unwrap() = Some(query.clone());
+    }
+    match get_weather(&query).await {
+        Ok(weather) => Ok(axum::Json(weather)),
+        Err(err) => {
+            tracing::warn!("Failed to fetch weather: {}", err);
+            Err(http::StatusCode::BAD_GATEWAY)
+        }
+    }
+}
+
+/// Candidate matches for a place name, so apps can offer a choice.
+pub async fn geocode_json(
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>
+) -> Result<axum::Json<Vec<LocationCandidate>>, http::StatusCode> {
+    let query = params.get("q").ok_or(http::StatusCode::BAD_REQUEST)?;
+    match search_locations(query).await {
+        Ok(candidates) => Ok(axum::Json(candidates)),
+        Err(err) => {
+            tracing::warn!("Failed to search locations: {}", err);
+            Err(http::StatusCode::BAD_GATEWAY)
+        }
+    }
 }